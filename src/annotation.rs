@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use skia_safe::{Color, Image, Paint, Rect, surfaces};
+
+use crate::text;
+
+/// Returns the label for the image at `index` in the file at `path`, naming the file and the
+/// page, counted from 1.
+pub fn page_label(path: &Path, index: usize) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    format!("{stem}, page {}", index + 1)
+}
+
+/// Returns a copy of `image` with `label` drawn in its bottom left corner, for exports where
+/// it matters which page they came from. The text is sized to the image, and drawn on a dark
+/// box so that it can be read over anything. Returns `None` if it couldn't be drawn.
+pub fn label_image(image: &Image, label: &str) -> Option<Image> {
+    let mut surface = surfaces::raster_n32_premul(image.dimensions())?;
+    let canvas = surface.canvas();
+    canvas.draw_image(image, (0.0, 0.0), None);
+
+    let font = text::font((image.height() as f32 / 40.0).max(12.0));
+    let (_, metrics) = font.metrics();
+    let padding = font.size() / 2.0;
+    let text_width = font.measure_str(label, None).0;
+
+    let bottom = image.height() as f32 - padding;
+    let top = bottom - (metrics.descent - metrics.ascent) - 2.0 * padding;
+
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_rect(
+        Rect {
+            left: padding,
+            top,
+            right: padding + text_width + 2.0 * padding,
+            bottom,
+        },
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    canvas.draw_str(
+        label,
+        (2.0 * padding, top + padding - metrics.ascent),
+        &font,
+        &paint,
+    );
+
+    Some(surface.image_snapshot())
+}
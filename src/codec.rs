@@ -0,0 +1,635 @@
+use std::{cell::RefCell, fmt, sync::OnceLock, time::Duration};
+
+use skia_safe::{
+    Data, EncodedImageFormat, EncodedOrigin, FilterMode, IRect, ISize, Image, ImageInfo,
+    MipmapMode, Paint, Rect, SamplingOptions,
+    canvas::SrcRectConstraint,
+    codec::{
+        self, Codec, FrameInfo, ScanlineOrder, ZeroInitialized, codec_animation::DisposalMethod,
+        codecs, gif_decoder, jpeg_decoder, png_decoder, webp_decoder,
+    },
+    images, surfaces,
+};
+
+/// An image which has been decoded into memory.
+#[derive(Clone)]
+pub struct DecodedImage {
+    pub image: Image,
+    pub info: ImageInfo,
+}
+
+/// A frame of an animated image, or the only frame of a still image.
+#[derive(Clone)]
+pub struct Frame {
+    pub image: DecodedImage,
+    /// How long the frame is shown for, as given by the image
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The bytes aren't in any format which skia can decode
+    UnsupportedFormat,
+    /// The bytes are in a format which is recognized, but skia wasn't built with a decoder for
+    NotBuiltIn(ImageFormat),
+    /// The bytes are in a supported format, but couldn't be decoded
+    Codec(codec::Result),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat => write!(f, "unsupported file type"),
+            Self::NotBuiltIn(format) => {
+                write!(f, "{} is not supported by this build", format.name())
+            }
+            Self::Codec(result) => write!(f, "failed to decode image: {result:?}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Avif,
+}
+
+impl ImageFormat {
+    /// Detects the format of an encoded image from its magic bytes. Bytes which have the magic
+    /// bytes of more than one format are detected as the first of JPEG, PNG, WebP, GIF, and
+    /// AVIF which they match.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        [Self::Jpeg, Self::Png, Self::Webp, Self::Gif, Self::Avif]
+            .into_iter()
+            .find(|format| format.matches(bytes))
+    }
+
+    /// Returns whether `bytes` start with the magic bytes of the format, the same way as skia's
+    /// decoder for it checks them.
+    fn matches(self, bytes: &[u8]) -> bool {
+        match self {
+            Self::Jpeg => bytes.starts_with(b"\xFF\xD8\xFF"),
+            Self::Png => bytes.starts_with(b"\x89PNG\x0D\x0A\x1A\x0A"),
+            Self::Webp => {
+                bytes.len() > b"RIFF\0\0\0\0WEBPVP".len()
+                    && bytes.starts_with(b"RIFF")
+                    && &bytes[8..][..6] == b"WEBPVP"
+            }
+            Self::Gif => bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+            Self::Avif => {
+                bytes.len() >= 12
+                    && &bytes[4..8] == b"ftyp"
+                    && matches!(&bytes[8..12], b"avif" | b"avis")
+            }
+        }
+    }
+
+    /// Returns the name of the format, for messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::Webp => "WebP",
+            Self::Gif => "GIF",
+            Self::Avif => "AVIF",
+        }
+    }
+
+    /// Returns the conventional file extension for the format, without a leading `.`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Gif => "gif",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// Parses the name of a format which can be decoded, as in [`parse_decode_order`].
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "jpeg" | "jpg" => Self::Jpeg,
+            "png" => Self::Png,
+            "webp" => Self::Webp,
+            "gif" => Self::Gif,
+            _ => return None,
+        })
+    }
+
+    /// Returns skia's decoder for the format, if it was built with one.
+    fn decoder(self) -> Option<codecs::Decoder> {
+        Some(match self {
+            Self::Jpeg => jpeg_decoder::decoder(),
+            Self::Png => png_decoder::decoder(),
+            Self::Webp => webp_decoder::decoder(),
+            Self::Gif => gif_decoder::decoder(),
+            Self::Avif => return None,
+        })
+    }
+
+    /// Returns whether skia was built with a decoder for the format. WebP needs the
+    /// `webp-decode` feature of skia-safe, and there's no AVIF decoder.
+    fn is_decodable(self) -> bool {
+        match self {
+            Self::Jpeg | Self::Png | Self::Webp | Self::Gif => true,
+            Self::Avif => false,
+        }
+    }
+}
+
+/// The formats whose decoders are tried first, in order, unless another order is set: JPEG, PNG,
+/// then WebP. Any other format is decoded by whichever of skia's decoders recognizes it.
+const DEFAULT_DECODE_ORDER: [ImageFormat; 3] =
+    [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Webp];
+
+static DECODE_ORDER: OnceLock<Vec<ImageFormat>> = OnceLock::new();
+
+thread_local! {
+    /// The order set by the container whose images are being decoded on this thread, which
+    /// takes the place of [`DECODE_ORDER`]
+    static CONTAINER_DECODE_ORDER: RefCell<Option<Vec<ImageFormat>>> = const { RefCell::new(None) };
+}
+
+/// Sets which formats' decoders are tried first when decoding, in order, so that bytes which
+/// more than one decoder recognizes are always decoded the same way. It can only be set once,
+/// before anything is decoded, so setting it again fails, returning the order which was passed.
+pub fn set_decode_order(order: Vec<ImageFormat>) -> Result<(), Vec<ImageFormat>> {
+    DECODE_ORDER.set(order)
+}
+
+/// Calls `f` with `order`, if there is one, in place of the order set by [`set_decode_order`]
+/// for anything it decodes on this thread. This is for decoding the images of a container which
+/// sets its own order in its [`Metadata`](crate::Metadata).
+pub fn with_decode_order<T>(order: Option<&[ImageFormat]>, f: impl FnOnce() -> T) -> T {
+    let Some(order) = order else {
+        return f();
+    };
+
+    let previous = CONTAINER_DECODE_ORDER.replace(Some(order.to_vec()));
+    let result = f();
+    CONTAINER_DECODE_ORDER.set(previous);
+
+    result
+}
+
+/// Returns the first of the formats in `order` whose magic bytes `bytes` start with, which is
+/// the one whose decoder skia picks when it's given them in that order.
+fn preferred_format(bytes: &[u8], order: &[ImageFormat]) -> Option<ImageFormat> {
+    order.iter().copied().find(|format| format.matches(bytes))
+}
+
+/// Parses an order of formats to decode with, as a comma separated list of `jpeg`, `png`,
+/// `webp`, and `gif`, each at most once.
+pub fn parse_decode_order(s: &str) -> Option<Vec<ImageFormat>> {
+    let mut order = Vec::new();
+    for name in s.split(',') {
+        let format = ImageFormat::parse(name.trim())?;
+        if order.contains(&format) {
+            return None;
+        }
+        order.push(format);
+    }
+
+    Some(order)
+}
+
+/// Decodes an image in any format which skia supports, detecting the format from its contents.
+pub fn decode_image(bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+    decode_image_scaled(bytes, 1.0)
+}
+
+/// Decodes an image like [`decode_image`], but at approximately `scale` times its full size.
+/// This is faster than decoding at full size for formats which support it, like JPEG. Other
+/// formats are decoded at full size.
+pub fn decode_image_scaled(bytes: &[u8], scale: f32) -> Result<DecodedImage, DecodeError> {
+    with_codec(bytes, |codec| decode_scaled(codec, scale))
+}
+
+/// Decodes an image like [`decode_image_scaled`], at about the smallest size which covers
+/// `size`, such as for a thumbnail. Images which are already smaller are decoded at full size.
+pub fn decode_image_to_cover(bytes: &[u8], size: ISize) -> Result<DecodedImage, DecodeError> {
+    with_codec(bytes, |codec| {
+        let ISize { width, height } = upright_dimensions(codec);
+        let scale = (size.width as f32 / width as f32).max(size.height as f32 / height as f32);
+
+        decode_scaled(codec, scale)
+    })
+}
+
+fn decode_scaled(codec: &mut Codec, scale: f32) -> Result<DecodedImage, DecodeError> {
+    let info = if scale < 1.0 {
+        codec
+            .info()
+            .with_dimensions(codec.get_scaled_dimensions(scale))
+    } else {
+        codec.info()
+    };
+
+    let image = codec
+        .get_image(info.clone(), None)
+        .map_err(DecodeError::Codec)?;
+
+    orient(DecodedImage { image, info }, codec.origin())
+}
+
+/// Returns a copy of an image scaled down so that its longer side is at most `max_size` pixels,
+/// encoded as a JPEG, such as for the thumbnail of a container.
+pub fn encode_thumbnail(bytes: &[u8], max_size: i32) -> Result<Vec<u8>, DecodeError> {
+    let DecodedImage { image, info } = with_codec(bytes, |codec| {
+        let ISize { width, height } = upright_dimensions(codec);
+        decode_scaled(codec, max_size as f32 / width.max(height) as f32)
+    })?;
+
+    // Only some formats scale while decoding, and only by some factors, so the rest of the way
+    // is done by drawing the image smaller
+    let ISize { width, height } = info.dimensions();
+    let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+    let size = ISize::new(
+        ((width as f32 * scale).round() as i32).max(1),
+        ((height as f32 * scale).round() as i32).max(1),
+    );
+
+    let internal_error = || DecodeError::Codec(codec::Result::InternalError);
+    let mut surface = surfaces::raster_n32_premul(size).ok_or_else(internal_error)?;
+    surface.canvas().draw_image_rect_with_sampling_options(
+        &image,
+        None::<(&Rect, SrcRectConstraint)>,
+        Rect::from_iwh(size.width, size.height),
+        SamplingOptions::new(FilterMode::Linear, MipmapMode::Linear),
+        &Paint::default(),
+    );
+
+    surface
+        .image_snapshot()
+        .encode(None, EncodedImageFormat::JPEG, 85)
+        .map(|data| data.as_bytes().to_vec())
+        .ok_or_else(internal_error)
+}
+
+/// Decodes an image at a reduced resolution of about `max_pixels` pixels, as an overview of an
+/// image which is too large to decode whole. Formats which can't be scaled while they're
+/// decoded, like PNG, are sampled a row at a time instead, so that the whole image is never in
+/// memory.
+pub fn decode_overview(bytes: &[u8], max_pixels: u64) -> Result<DecodedImage, DecodeError> {
+    with_codec(bytes, |codec| {
+        let scale = (max_pixels as f64 / pixel_count(codec.dimensions()) as f64)
+            .sqrt()
+            .min(1.0) as f32;
+
+        // Formats which scale while decoding only do so by some factors, like JPEG's eighths,
+        // which may still be too large
+        if pixel_count(codec.get_scaled_dimensions(scale)) <= 2 * max_pixels {
+            return decode_scaled(codec, scale);
+        }
+
+        let step = (1.0 / scale).ceil() as usize;
+        decode_sampled(codec, step)
+    })
+}
+
+/// Decodes every `step`th pixel of every `step`th row of an image.
+fn decode_sampled(codec: &mut Codec, step: usize) -> Result<DecodedImage, DecodeError> {
+    let info = codec.info();
+    match codec.start_scanline_decode(&info, None) {
+        codec::Result::Success => {}
+        result => return Err(DecodeError::Codec(result)),
+    }
+    // Such as interlaced images, whose rows can't be read one at a time
+    if codec.scanline_order() != ScanlineOrder::TopDown {
+        return Err(DecodeError::Codec(codec::Result::Unimplemented));
+    }
+
+    let width = usize::try_from(info.width()).unwrap();
+    let height = usize::try_from(info.height()).unwrap();
+    let sampled_info = info.with_dimensions(ISize::new(
+        i32::try_from(width.div_ceil(step)).unwrap(),
+        i32::try_from(height.div_ceil(step)).unwrap(),
+    ));
+
+    let row_bytes = info.min_row_bytes();
+    let mut row = vec![0; row_bytes];
+    let mut pixels = Vec::with_capacity(sampled_info.compute_min_byte_size());
+    for y in (0..height).step_by(step) {
+        if y > 0 {
+            codec.skip_scanlines(step - 1);
+        }
+        // Rows past the end of a truncated image are left blank
+        if codec.get_scanlines(&mut row, 1, row_bytes) == 0 {
+            row.fill(0);
+        }
+
+        for pixel in row.chunks_exact(info.bytes_per_pixel()).step_by(step) {
+            pixels.extend_from_slice(pixel);
+        }
+    }
+
+    let sampled_row_bytes = sampled_info.min_row_bytes();
+    let image = images::raster_from_data(&sampled_info, Data::new_copy(&pixels), sampled_row_bytes)
+        .ok_or(DecodeError::Codec(codec::Result::InternalError))?;
+
+    orient(
+        DecodedImage {
+            image,
+            info: sampled_info,
+        },
+        codec.origin(),
+    )
+}
+
+/// Decodes the part of an image within `region` at full size, without decoding the rest of it
+/// into memory, for images which are too large to decode whole. `region` has to be within the
+/// image, and start at even coordinates, since WebP can only start decoding at them. Only images
+/// which are encoded upright are supported.
+pub fn decode_region(bytes: &[u8], region: IRect) -> Result<DecodedImage, DecodeError> {
+    with_codec(bytes, |codec| {
+        // Regions of the upright image would need mapping back to where they're encoded
+        if codec.origin() != EncodedOrigin::TopLeft {
+            return Err(DecodeError::Codec(codec::Result::Unimplemented));
+        }
+
+        let ISize { width, height } = codec.dimensions();
+        if region.left < 0
+            || region.top < 0
+            || region.right > width
+            || region.bottom > height
+            || region.left >= region.right
+            || region.top >= region.bottom
+        {
+            return Err(DecodeError::Codec(codec::Result::InvalidParameters));
+        }
+
+        let info = codec.info().with_dimensions(ISize::new(
+            region.right - region.left,
+            region.bottom - region.top,
+        ));
+        let mut options = codec::Options {
+            zero_initialized: ZeroInitialized::Yes,
+            subset: Some(region),
+            frame_index: 0,
+            prior_frame: None,
+        };
+
+        // Formats which can decode a subset, like WebP, skip over the rest of the image
+        if codec.valid_subset(region) == Some(region) {
+            let image = codec
+                .get_image(info.clone(), &options)
+                .map_err(DecodeError::Codec)?;
+            return Ok(DecodedImage { image, info });
+        }
+
+        // Others decode whole rows, which can be narrowed down to the region's columns, but not
+        // started partway down the image
+        options.subset = Some(IRect {
+            left: region.left,
+            top: 0,
+            right: region.right,
+            bottom: height,
+        });
+        let scanline_info = info.with_dimensions(ISize::new(region.right - region.left, height));
+        match codec.start_scanline_decode(&scanline_info, &options) {
+            codec::Result::Success => {}
+            result => return Err(DecodeError::Codec(result)),
+        }
+        if codec.scanline_order() != ScanlineOrder::TopDown {
+            return Err(DecodeError::Codec(codec::Result::Unimplemented));
+        }
+
+        let row_bytes = info.min_row_bytes();
+        let mut pixels = vec![0; info.compute_min_byte_size()];
+        // Rows past the end of a truncated image are left blank
+        let rows = usize::try_from(region.bottom - region.top).unwrap();
+        if codec.skip_scanlines(usize::try_from(region.top).unwrap()) {
+            codec.get_scanlines(&mut pixels, rows, row_bytes);
+        }
+
+        let image = images::raster_from_data(&info, Data::new_copy(&pixels), row_bytes)
+            .ok_or(DecodeError::Codec(codec::Result::InternalError))?;
+        Ok(DecodedImage { image, info })
+    })
+}
+
+/// Returns the number of pixels in an image of `size`.
+pub fn pixel_count(size: ISize) -> u64 {
+    u64::try_from(size.width).unwrap_or(0) * u64::try_from(size.height).unwrap_or(0)
+}
+
+/// Decodes every frame of an image at full size. Still images have a single frame.
+pub fn decode_frames(bytes: &[u8]) -> Result<Vec<Frame>, DecodeError> {
+    with_codec(bytes, |codec| {
+        let info = codec.info();
+        let row_bytes = info.min_row_bytes();
+
+        // The last frame decoded, which the next one can be drawn on top of
+        let mut previous: Option<(usize, FrameInfo, Vec<u8>)> = None;
+        let mut frames = Vec::new();
+
+        for frame_index in 0..codec.get_frame_count().max(1) {
+            let frame_info = codec.get_frame_info(frame_index);
+
+            // Frames which are drawn on top of the one before them start from its pixels.
+            // Otherwise the codec decodes every frame back to the last full one again, which
+            // takes time quadratic in the number of frames.
+            let prior = previous.take().filter(|(prior_index, prior_info, _)| {
+                frame_info
+                    .is_some_and(|frame_info| can_draw_on(&frame_info, *prior_index, prior_info))
+            });
+            let prior_frame = prior.as_ref().map(|(prior_index, _, _)| *prior_index);
+            let mut pixels = match prior {
+                Some((_, _, pixels)) => pixels,
+                None => vec![0; info.compute_min_byte_size()],
+            };
+
+            let options = codec::Options {
+                zero_initialized: if prior_frame.is_some() {
+                    ZeroInitialized::No
+                } else {
+                    ZeroInitialized::Yes
+                },
+                subset: None,
+                frame_index,
+                prior_frame,
+            };
+            match codec.get_pixels_with_options(&info, &mut pixels, row_bytes, Some(&options)) {
+                // Like skia's own decoding into an image, show as much of a truncated or
+                // partly corrupt frame as could be decoded
+                codec::Result::Success
+                | codec::Result::IncompleteInput
+                | codec::Result::ErrorInInput => {}
+                result => return Err(DecodeError::Codec(result)),
+            }
+
+            let image = images::raster_from_data(&info, Data::new_copy(&pixels), row_bytes)
+                .ok_or(DecodeError::Codec(codec::Result::InternalError))?;
+            let image = DecodedImage {
+                image,
+                info: info.clone(),
+            };
+
+            let duration = frame_info.map_or(0, |frame_info| frame_info.duration);
+            frames.push(Frame {
+                image: orient(image, codec.origin())?,
+                duration: Duration::from_millis(u64::try_from(duration).unwrap_or(0)),
+            });
+
+            previous = frame_info.map(|frame_info| (frame_index, frame_info, pixels));
+        }
+
+        Ok(frames)
+    })
+}
+
+/// Returns whether a frame can be decoded on top of the pixels of the frame at `prior_index`,
+/// which skia only allows when the frame depends on it or a frame before it, and it isn't
+/// replaced by what was under it once it's done.
+fn can_draw_on(frame_info: &FrameInfo, prior_index: usize, prior_info: &FrameInfo) -> bool {
+    frame_info.required_frame != codec::NO_FRAME
+        && usize::try_from(frame_info.required_frame).is_ok_and(|required| required <= prior_index)
+        && prior_info.disposal_method != DisposalMethod::RestorePrevious
+}
+
+/// Reads the dimensions of an image from its header, without decoding the rest of it. Like
+/// decoded images, the dimensions are of the image once it's upright.
+pub fn image_dimensions(bytes: &[u8]) -> Result<ISize, DecodeError> {
+    with_codec(bytes, |codec| Ok(upright_dimensions(codec)))
+}
+
+fn upright_dimensions(codec: &Codec) -> ISize {
+    let ISize { width, height } = codec.dimensions();
+
+    if codec.origin().swaps_width_height() {
+        ISize::new(height, width)
+    } else {
+        ISize::new(width, height)
+    }
+}
+
+/// Rotates and flips a decoded image as the orientation it was encoded with says to, such as
+/// from the EXIF data of a photo, so that it's upright.
+fn orient(decoded_image: DecodedImage, origin: EncodedOrigin) -> Result<DecodedImage, DecodeError> {
+    if origin == EncodedOrigin::TopLeft {
+        return Ok(decoded_image);
+    }
+
+    let DecodedImage { image, info } = decoded_image;
+
+    let mut dimensions = info.dimensions();
+    if origin.swaps_width_height() {
+        dimensions = ISize::new(dimensions.height, dimensions.width);
+    }
+
+    let mut surface = surfaces::raster_n32_premul(dimensions)
+        .ok_or(DecodeError::Codec(codec::Result::InternalError))?;
+    let canvas = surface.canvas();
+    canvas.concat(&origin.to_matrix(info.dimensions()));
+    canvas.draw_image(image, (0.0, 0.0), None);
+
+    Ok(DecodedImage {
+        image: surface.image_snapshot(),
+        info: info.with_dimensions(dimensions),
+    })
+}
+
+fn with_codec<T>(
+    bytes: &[u8],
+    f: impl FnOnce(&mut Codec) -> Result<T, DecodeError>,
+) -> Result<T, DecodeError> {
+    // These are recognized so that they can be extracted and reported, but none of skia's
+    // decoders would take them
+    if let Some(format) = ImageFormat::detect(bytes).filter(|format| !format.is_decodable()) {
+        return Err(DecodeError::NotBuiltIn(format));
+    }
+
+    let format = CONTAINER_DECODE_ORDER.with_borrow(|container_order| {
+        let order = container_order
+            .as_deref()
+            .or(DECODE_ORDER.get().map(Vec::as_slice))
+            .unwrap_or(&DEFAULT_DECODE_ORDER);
+        preferred_format(bytes, order)
+    });
+
+    // Otherwise skia picks the decoder by sniffing the bytes, so every format it was built with
+    // works
+    let codec = format
+        .and_then(ImageFormat::decoder)
+        .and_then(|decoder| Codec::from_data_with_decoders(Data::new_copy(bytes), &[decoder]))
+        .or_else(|| Codec::from_data(Data::new_copy(bytes)));
+    let Some(mut codec) = codec else {
+        return Err(match ImageFormat::detect(bytes) {
+            // The format was recognized and can be decoded, so the header must be corrupt
+            Some(_) => DecodeError::Codec(codec::Result::InvalidInput),
+            None => DecodeError::UnsupportedFormat,
+        });
+    };
+
+    f(&mut codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_orders_are_read_in_the_order_given() {
+        assert_eq!(
+            parse_decode_order("png, jpg,gif"),
+            Some(vec![ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif])
+        );
+    }
+
+    #[test]
+    fn decode_orders_only_have_decodable_formats_once_each() {
+        assert_eq!(parse_decode_order("png,bmp"), None);
+        assert_eq!(parse_decode_order("avif"), None);
+        assert_eq!(parse_decode_order("jpeg,png,jpg"), None);
+        assert_eq!(parse_decode_order(""), None);
+    }
+
+    #[test]
+    fn ambiguous_bytes_are_decoded_as_the_first_format_in_the_order() {
+        // A JPEG's start of image marker, followed by an AVIF `ftyp` box where the JPEG's first
+        // segment would be
+        let bytes = b"\xFF\xD8\xFF\xE0ftypavif\0\0\0\0";
+        assert!(ImageFormat::Jpeg.matches(bytes) && ImageFormat::Avif.matches(bytes));
+
+        // It's decoded as a JPEG, rather than refused as an AVIF image
+        assert_eq!(ImageFormat::detect(bytes), Some(ImageFormat::Jpeg));
+        assert_eq!(
+            preferred_format(bytes, &DEFAULT_DECODE_ORDER),
+            Some(ImageFormat::Jpeg)
+        );
+        // Orders without JPEG leave it to skia's own sniffing
+        assert_eq!(
+            preferred_format(bytes, &[ImageFormat::Png, ImageFormat::Webp]),
+            None
+        );
+    }
+
+    #[test]
+    fn container_decode_orders_only_last_while_decoding_the_container() {
+        let current_order = || CONTAINER_DECODE_ORDER.with_borrow(Clone::clone);
+
+        let order = [ImageFormat::Gif, ImageFormat::Png];
+        let during = with_decode_order(Some(&order), current_order);
+
+        assert_eq!(during.as_deref(), Some(&order[..]));
+        assert_eq!(current_order(), None);
+        assert_eq!(with_decode_order(None, current_order), None);
+    }
+
+    #[test]
+    fn avif_images_are_detected_but_not_decoded() {
+        // The start of an AVIF file, with its `ftyp` box and compatible brands
+        let bytes = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+
+        assert_eq!(ImageFormat::detect(bytes), Some(ImageFormat::Avif));
+        let error = decode_image(bytes).err().unwrap();
+        assert!(matches!(error, DecodeError::NotBuiltIn(ImageFormat::Avif)));
+        assert_eq!(error.to_string(), "AVIF is not supported by this build");
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use gallery_desktop::{
+    DecodeError, DecodedImage, Frame, MappedContainer, OfcError, decode_frames,
+    decode_image_scaled, decode_overview, decode_region, image_dimensions, pixel_count,
+    with_decode_order,
+};
+use skia_safe::{IRect, ISize};
+
+use crate::image_cache::ImageCache;
+use crate::selector::{self, Cover};
+
+/// The most pixels to decode an overview of an image which is too large to decode whole at
+const OVERVIEW_PIXELS: u64 = 16 * 1024 * 1024;
+
+/// An image to decode, by the path of the file it's in and its index in it.
+#[derive(Clone)]
+pub struct Request {
+    pub path: PathBuf,
+    /// The file at `path`, which the image is decoded straight out of, without being copied
+    pub file: Arc<MappedContainer>,
+    pub index: usize,
+    /// The scale to decode the image at, as a preview, or `None` to decode all of its frames at
+    /// full size
+    pub preview_scale: Option<f32>,
+    /// Images with more pixels than this are decoded as an overview at reduced resolution
+    /// instead, with parts of them decoded at full size as they're needed
+    pub tile_above_pixels: Option<u64>,
+}
+
+impl PartialEq for Request {
+    fn eq(&self, other: &Self) -> bool {
+        // A file which was opened again may have changed since, so it's a different request
+        Arc::ptr_eq(&self.file, &other.file)
+            && self.path == other.path
+            && self.index == other.index
+            && self.preview_scale == other.preview_scale
+            && self.tile_above_pixels == other.tile_above_pixels
+    }
+}
+
+/// What was decoded on a background thread, sent back to the event loop.
+pub enum Decoded {
+    /// The image for a request
+    Image {
+        request: Request,
+        image: Result<Loaded, LoadError>,
+    },
+    /// The part within `region` of the image at `index` in the file at `path`, decoded at full
+    /// size
+    Region {
+        path: PathBuf,
+        index: usize,
+        region: IRect,
+        image: Result<DecodedImage, LoadError>,
+    },
+    /// The cover of the file at `path`, or `None` if it has no cover which can be decoded
+    Cover {
+        path: PathBuf,
+        image: Option<DecodedImage>,
+    },
+}
+
+/// What an image was decoded into.
+pub enum Loaded {
+    /// Every frame of the image, or a preview of it
+    Frames(Vec<Frame>),
+    /// An overview at reduced resolution of an image which is too large to decode whole, along
+    /// with the size of the image
+    Overview(DecodedImage, ISize),
+}
+
+/// The reasons an image can't be shown.
+pub enum LoadError {
+    /// Its file couldn't be read, such as if it's on a network drive which was disconnected
+    Read(OfcError),
+    Decode(DecodeError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "failed to read image: {e}"),
+            Self::Decode(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Passes on what was decoded, such as to the event loop, returning `false` once it's no longer
+/// wanted because the event loop has exited.
+type Notify = Arc<dyn Fn(Decoded) -> bool + Send + Sync>;
+
+/// Decodes images on a background thread so that slow decodes don't hold up handling input.
+/// Clones share the same thread.
+#[derive(Clone)]
+pub struct Decoder {
+    requests: Sender<Request>,
+    notify: Notify,
+}
+
+impl Decoder {
+    /// Starts the decoding thread, which passes each decoded image to `notify`.
+    pub fn new(notify: impl Fn(Decoded) -> bool + Send + Sync + 'static) -> Self {
+        let notify: Notify = Arc::new(notify);
+        let (requests, receiver) = mpsc::channel();
+        let worker_notify = notify.clone();
+        thread::spawn(move || decode(receiver, worker_notify));
+
+        Self { requests, notify }
+    }
+
+    /// Starts decoding an image, replacing any earlier request which hasn't been worked on yet.
+    pub fn decode(&self, request: Request) {
+        // This only fails if the worker panicked, in which case the image is never shown
+        let _ = self.requests.send(request);
+    }
+
+    /// Starts decoding the cover of the file at `path`, at about `size`, on its own thread so
+    /// that it doesn't wait behind images being navigated through.
+    pub fn decode_cover(&self, path: PathBuf, cover: Cover, cache: ImageCache, size: ISize) {
+        let notify = self.notify.clone();
+        thread::spawn(move || {
+            let image = selector::load_cover(&path, cover, &cache, size);
+            notify(Decoded::Cover { path, image });
+        });
+    }
+
+    /// Starts decoding the part within `region` of the image at `index` in `file`, which is at
+    /// `path`, at full size, on its own thread so that it doesn't hold up navigation.
+    pub fn decode_region(
+        &self,
+        path: PathBuf,
+        file: Arc<MappedContainer>,
+        index: usize,
+        region: IRect,
+    ) {
+        let notify = self.notify.clone();
+        thread::spawn(move || {
+            let image = load_region(&file, index, region);
+            notify(Decoded::Region {
+                path,
+                index,
+                region,
+                image,
+            });
+        });
+    }
+}
+
+fn decode(receiver: Receiver<Request>, notify: Notify) {
+    // This stops once every `Decoder` is dropped
+    while let Ok(mut request) = receiver.recv() {
+        // Navigation has moved on from all but the latest request
+        while let Ok(newer_request) = receiver.try_recv() {
+            request = newer_request;
+        }
+
+        let image = load(&request);
+
+        // Once the event loop has exited, nothing else needs decoding
+        if !notify(Decoded::Image { request, image }) {
+            break;
+        }
+    }
+}
+
+/// Decodes the image for `request` from the mapping of its file, in the decode order of the file
+/// if it has one.
+fn load(request: &Request) -> Result<Loaded, LoadError> {
+    with_decode_order(request.file.decode_order(), || load_with_order(request))
+}
+
+fn load_with_order(request: &Request) -> Result<Loaded, LoadError> {
+    let bytes = read(&request.file, request.index)?;
+
+    if let Some(max_pixels) = request.tile_above_pixels {
+        let size = image_dimensions(bytes).map_err(LoadError::Decode)?;
+        if pixel_count(size) > max_pixels {
+            return decode_overview(bytes, OVERVIEW_PIXELS)
+                .map(|overview| Loaded::Overview(overview, size))
+                .map_err(LoadError::Decode);
+        }
+    }
+
+    match request.preview_scale {
+        Some(scale) => decode_image_scaled(bytes, scale).map(|image| {
+            vec![Frame {
+                image,
+                duration: Duration::ZERO,
+            }]
+        }),
+        None => decode_frames(bytes),
+    }
+    .map(Loaded::Frames)
+    .map_err(LoadError::Decode)
+}
+
+fn load_region(
+    file: &MappedContainer,
+    index: usize,
+    region: IRect,
+) -> Result<DecodedImage, LoadError> {
+    let bytes = read(file, index)?;
+    with_decode_order(file.decode_order(), || decode_region(bytes, region))
+        .map_err(LoadError::Decode)
+}
+
+/// Returns the encoded bytes of the image at `index` in `file`, borrowed from its mapping.
+fn read(file: &MappedContainer, index: usize) -> Result<&[u8], LoadError> {
+    // Checked rather than left to panic, since a decoding thread which panicked would never
+    // decode anything again
+    if index >= file.len() {
+        return Err(LoadError::Read(OfcError::TruncatedData));
+    }
+
+    Ok(file.read_at(index))
+}
@@ -1,68 +1,439 @@
 use std::{
+    fmt,
     fs::File,
-    io::{Read, Seek},
+    io::{self, BufReader, Read, Seek, Take},
     path::Path,
 };
 
+use crate::{Chapter, ImageFormat, ImageSource, Metadata};
+
+/// The version of the format which is written, and the newest which can be read. It's stored in
+/// the byte after `ofc` at the start of a container. Version 1 adds [`Metadata`] after the
+/// images, and version 2 adds chapters and decode orders to it.
+pub const CURRENT_VERSION: u8 = 2;
+
+/// The reasons an ofc container can't be opened.
+#[derive(Debug)]
+pub enum OfcError {
+    Io(io::Error),
+    /// The file doesn't start with `ofc`, so it isn't a container
+    WrongMagic,
+    /// The container is in a newer version of the format than can be read
+    UnsupportedVersion(u8),
+    /// The file ends before the end of its offset table
+    TruncatedHeader,
+    /// An image ends before the one preceding it
+    NonMonotonicOffsets,
+    /// The file ends before the end of its last image
+    TruncatedData,
+    /// The metadata after the images is cut short or malformed
+    InvalidMetadata,
+}
+
+impl fmt::Display for OfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::WrongMagic => write!(f, "not an ofc file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported ofc version {version}")
+            }
+            Self::TruncatedHeader => write!(f, "header is truncated"),
+            Self::NonMonotonicOffsets => write!(f, "image offsets are out of order"),
+            Self::TruncatedData => write!(f, "images are truncated"),
+            Self::InvalidMetadata => write!(f, "metadata is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for OfcError {}
+
+impl From<io::Error> for OfcError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Self::TruncatedHeader,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+/// An ofc container on disk, whose images are read lazily.
 pub struct FileContainer {
     f: File,
-    end_offsets: Vec<u64>,
+    /// The version of the format the container is in
+    version: u8,
+    end_offsets: EndOffsets,
+}
+
+/// The end offset of each image in a container.
+enum EndOffsets {
+    /// The whole offset table, read when the container was opened
+    Eager(Vec<u64>),
+    /// Only the number of images, and the length of the file to check offsets against. Offsets
+    /// are read from the table as they're needed.
+    Lazy { len: usize, file_len: u64 },
 }
 
 impl FileContainer {
-    pub fn open(p: &Path) -> Self {
-        let mut f = File::open(p).unwrap();
+    /// Opens the container at `p` and reads its offset table.
+    pub fn open(p: &Path) -> Result<Self, OfcError> {
+        let f = File::open(p)?;
+        let file_len = f.metadata()?.len();
 
-        let mut buf = [0u8; 8];
-        f.read_exact(&mut buf).unwrap();
+        // Buffer reads so that the header is read along with the offset table, rather than with
+        // a syscall of its own
+        let mut reader = BufReader::new(f);
 
-        assert_eq!(&buf[..4], b"ofc\0");
+        let (version, end_offsets) = read_end_offsets(&mut reader)?;
+        check_data_len(&end_offsets, file_len)?;
 
-        let num_files = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let mut end_offsets = vec![0; usize::try_from(num_files).unwrap()];
+        // Reading ahead leaves the file's position past the table, but `read_at` always seeks
+        // before reading
+        let f = reader.into_inner();
 
-        let mut offsets_buf = vec![0; end_offsets.len() * 8];
-        f.read_exact(&mut offsets_buf).unwrap();
+        Ok(Self {
+            f,
+            version,
+            end_offsets: EndOffsets::Eager(end_offsets),
+        })
+    }
 
-        for (i, chunk) in offsets_buf.chunks_exact(8).enumerate() {
-            end_offsets[i] = u64::from_le_bytes([
-                chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
-            ]);
+    /// Opens the container at `p` like [`FileContainer::open`], but only reads its header.
+    /// Offsets are read from the table when each image is, which makes opening containers with
+    /// very many images fast when only a few of them are needed. Offsets which are out of order
+    /// aren't noticed until the images they're for are read.
+    pub fn open_lazy(p: &Path) -> Result<Self, OfcError> {
+        let mut f = File::open(p)?;
+        let file_len = f.metadata()?.len();
+
+        let (version, len) = read_header(&mut f)?;
+        let len = usize::try_from(len).unwrap();
+        let mut container = Self {
+            f,
+            version,
+            end_offsets: EndOffsets::Lazy { len, file_len },
+        };
+
+        if container.data_end()? > file_len {
+            return Err(OfcError::TruncatedData);
         }
 
-        Self { f, end_offsets }
+        Ok(container)
     }
 
-    pub fn read_at(&mut self, i: usize) -> Vec<u8> {
-        assert!(i < self.len());
+    /// Reads the metadata stored after the images. Containers from before version 1 of the
+    /// format have none.
+    pub fn metadata(&mut self) -> Result<Metadata, OfcError> {
+        if self.version < 1 {
+            return Ok(Metadata::default());
+        }
 
-        if i == 0 {
-            self.f
-                .seek(std::io::SeekFrom::Start(
-                    8 + 8 * u64::try_from(self.len()).unwrap(),
-                ))
-                .unwrap();
+        let data_end = self.data_end()?;
+        let file_len = self.f.metadata()?.len();
 
-            let mut buf = vec![0u8; usize::try_from(self.end_offsets[0]).unwrap()];
-            self.f.read_exact(&mut buf).unwrap();
+        self.f.seek(io::SeekFrom::Start(data_end))?;
+        let mut bytes = Vec::new();
+        (&mut self.f)
+            .take(file_len.saturating_sub(data_end))
+            .read_to_end(&mut bytes)?;
 
-            return buf;
-        }
+        Metadata::parse(&bytes, self.version)
+    }
+
+    /// Reads where each chapter of the container starts, leaving out any which start past its
+    /// last image. Containers from before version 2 of the format have none.
+    pub fn chapters(&mut self) -> Result<Vec<Chapter>, OfcError> {
+        let mut chapters = self.metadata()?.chapters;
+        chapters.retain(|chapter| chapter.index < self.len());
+
+        Ok(chapters)
+    }
 
-        let prev_offset = self.end_offsets[i - 1];
-        self.f
-            .seek(std::io::SeekFrom::Start(
-                8 + 8 * u64::try_from(self.len()).unwrap() + prev_offset,
+    /// Reads the order to try decoders in for the container's images, if it sets one. Containers
+    /// whose metadata can't be read are decoded like any other.
+    pub fn decode_order(&mut self) -> Option<Vec<ImageFormat>> {
+        self.metadata().ok()?.decode_order
+    }
+
+    /// Returns the encoded bytes of the image at index `i`. This fails if the file can't be
+    /// read, such as if it's on a network drive which was disconnected after it was opened.
+    pub fn read_at(&mut self, i: usize) -> Result<Vec<u8>, OfcError> {
+        let mut reader = self.reader_at(i)?;
+
+        // Offsets are 64-bit everywhere, so this only fails for images which are larger than
+        // the address space, such as ones over 4 GB on 32-bit targets
+        let len = usize::try_from(reader.limit()).map_err(|_| {
+            OfcError::Io(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "image is too large to read into memory",
             ))
-            .unwrap();
+        })?;
+        let mut buf = vec![0u8; len];
+        reader.read_exact(&mut buf).map_err(OfcError::Io)?;
+
+        Ok(buf)
+    }
+
+    /// Returns a reader of the encoded bytes of the image at index `i`, so that they can be
+    /// read in chunks rather than all at once, such as for images too large to hold in memory.
+    pub fn reader_at(&mut self, i: usize) -> Result<Take<&mut File>, OfcError> {
+        let (start, end) = self.range(i)?;
+
+        // Offsets come from the file, so they're kept from wrapping around even though `range`
+        // has checked them against its length
+        let position = self
+            .data_start()
+            .checked_add(start)
+            .ok_or(OfcError::TruncatedData)?;
+        self.f.seek(std::io::SeekFrom::Start(position))?;
+
+        Ok((&mut self.f).take(end - start))
+    }
+
+    /// Returns the size in bytes of the encoded image at index `i`, from the offset table and
+    /// without reading the image.
+    pub fn image_len(&mut self, i: usize) -> Result<u64, OfcError> {
+        let (start, end) = self.range(i)?;
+
+        Ok(end - start)
+    }
+
+    /// Returns the start and end offsets of the image at index `i`, relative to the end of the
+    /// offset table.
+    fn range(&mut self, i: usize) -> Result<(u64, u64), OfcError> {
+        assert!(i < self.len());
 
-        let mut buf = vec![0u8; usize::try_from(self.end_offsets[i] - prev_offset).unwrap()];
-        self.f.read_exact(&mut buf).unwrap();
+        let start = match i {
+            0 => 0,
+            _ => self.end_offset(i - 1)?,
+        };
+        let end = self.end_offset(i)?;
+        if start > end {
+            return Err(OfcError::NonMonotonicOffsets);
+        }
+        // Only the last offset was checked against the file when it was opened lazily, so any
+        // other one could point past its end
+        if let EndOffsets::Lazy { file_len, .. } = self.end_offsets
+            && end > file_len.saturating_sub(self.data_start())
+        {
+            return Err(OfcError::TruncatedData);
+        }
 
-        buf
+        Ok((start, end))
     }
 
     pub fn len(&self) -> usize {
-        self.end_offsets.len()
+        match &self.end_offsets {
+            EndOffsets::Eager(end_offsets) => end_offsets.len(),
+            EndOffsets::Lazy { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the end offset of the image at index `i`, relative to the end of the offset
+    /// table.
+    fn end_offset(&mut self, i: usize) -> Result<u64, OfcError> {
+        if let EndOffsets::Eager(end_offsets) = &self.end_offsets {
+            return Ok(end_offsets[i]);
+        }
+
+        // The table has a fixed stride, following the 8 byte header
+        let position = 8 + 8 * u64::try_from(i).unwrap();
+        self.f.seek(std::io::SeekFrom::Start(position))?;
+
+        let mut buf = [0u8; 8];
+        self.f.read_exact(&mut buf)?;
+
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Returns the position in the file of the start of the first image.
+    fn data_start(&self) -> u64 {
+        8 + 8 * u64::try_from(self.len()).unwrap()
+    }
+
+    /// Returns the position in the file of the end of the last image.
+    fn data_end(&mut self) -> Result<u64, OfcError> {
+        let data_len = match self.len() {
+            0 => 0,
+            len => self.end_offset(len - 1)?,
+        };
+
+        self.data_start()
+            .checked_add(data_len)
+            .ok_or(OfcError::TruncatedData)
+    }
+}
+
+impl ImageSource for FileContainer {
+    fn read_at(&mut self, i: usize) -> Result<Vec<u8>, OfcError> {
+        FileContainer::read_at(self, i)
+    }
+
+    fn len(&self) -> usize {
+        FileContainer::len(self)
+    }
+}
+
+/// Reads the header and offset table at the start of an ofc container, returning the version
+/// of the format it's in and the end offset of each image.
+pub(crate) fn read_end_offsets(reader: &mut impl Read) -> Result<(u8, Vec<u64>), OfcError> {
+    let (version, num_files) = read_header(reader)?;
+    let mut end_offsets = vec![0; usize::try_from(num_files).unwrap()];
+
+    // On 32-bit targets, the table of a container with over half a billion images doesn't fit
+    // in memory. `FileContainer::open_lazy` can still open it.
+    let table_len = end_offsets.len().checked_mul(8).ok_or_else(|| {
+        OfcError::Io(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            "offset table is too large to read into memory",
+        ))
+    })?;
+    let mut offsets_buf = vec![0; table_len];
+    reader.read_exact(&mut offsets_buf)?;
+
+    for (i, chunk) in offsets_buf.chunks_exact(8).enumerate() {
+        end_offsets[i] = u64::from_le_bytes([
+            chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+        ]);
+    }
+
+    if end_offsets.is_sorted() {
+        Ok((version, end_offsets))
+    } else {
+        Err(OfcError::NonMonotonicOffsets)
+    }
+}
+
+/// Reads the 8 byte header at the start of an ofc container, returning the version of the format
+/// it's in and the number of images in it.
+fn read_header(reader: &mut impl Read) -> Result<(u8, u32), OfcError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+
+    if &buf[..3] != b"ofc" {
+        return Err(OfcError::WrongMagic);
+    }
+    if buf[3] > CURRENT_VERSION {
+        return Err(OfcError::UnsupportedVersion(buf[3]));
+    }
+
+    Ok((buf[3], u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]])))
+}
+
+/// Checks that a container which is `file_len` bytes long is long enough to hold every image in
+/// its offset table.
+pub(crate) fn check_data_len(end_offsets: &[u64], file_len: u64) -> Result<(), OfcError> {
+    let data_start = 8 + 8 * u64::try_from(end_offsets.len()).unwrap();
+    let data_len = end_offsets.last().copied().unwrap_or(0);
+
+    if data_start
+        .checked_add(data_len)
+        .is_none_or(|data_end| data_end > file_len)
+    {
+        return Err(OfcError::TruncatedData);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+
+    /// Writes a container with the given offset table, followed by `data_len` bytes of images.
+    fn write_table(name: &str, end_offsets: &[u64], data_len: usize) -> PathBuf {
+        let mut bytes = b"ofc".to_vec();
+        bytes.push(CURRENT_VERSION);
+        bytes.extend_from_slice(&u32::try_from(end_offsets.len()).unwrap().to_le_bytes());
+        for end_offset in end_offsets {
+            bytes.extend_from_slice(&end_offset.to_le_bytes());
+        }
+        bytes.resize(bytes.len() + data_len, 0);
+
+        let path = env::temp_dir().join(format!("gallery-desktop-{name}-{}.ofc", process::id()));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn lazy_offsets_past_the_end_of_the_file_are_errors() {
+        let path = write_table("lazy-past-end", &[u64::MAX - 4, u64::MAX - 2, 10], 10);
+
+        let mut file = FileContainer::open_lazy(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(file.read_at(0), Err(OfcError::TruncatedData)));
+        assert!(matches!(file.image_len(1), Err(OfcError::TruncatedData)));
+        assert!(matches!(
+            file.read_at(2),
+            Err(OfcError::NonMonotonicOffsets)
+        ));
+    }
+
+    #[test]
+    fn offsets_which_would_overflow_are_errors() {
+        let path = write_table("overflowing-offsets", &[u64::MAX, u64::MAX, 10], 10);
+
+        assert!(matches!(
+            FileContainer::open(&path),
+            Err(OfcError::NonMonotonicOffsets)
+        ));
+        let mut file = FileContainer::open_lazy(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(file.reader_at(0), Err(OfcError::TruncatedData)));
+        assert!(matches!(file.reader_at(1), Err(OfcError::TruncatedData)));
+    }
+
+    #[test]
+    fn only_containers_from_version_1_on_have_metadata() {
+        let path = write_table("metadata-versions", &[4], 4);
+        let mut bytes = fs::read(&path).unwrap();
+        // Too short to be a section, so it's only read as metadata by newer versions
+        bytes.extend_from_slice(b"thmb");
+        fs::write(&path, &bytes).unwrap();
+        let mut current = FileContainer::open(&path).unwrap();
+
+        bytes[3] = 0;
+        fs::write(&path, &bytes).unwrap();
+        let mut version_0 = FileContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(current.metadata(), Err(OfcError::InvalidMetadata)));
+        assert_eq!(version_0.metadata().unwrap(), Metadata::default());
+    }
+
+    #[test]
+    fn only_containers_from_version_2_on_have_chapters() {
+        let path = write_table("chapter-versions", &[4], 4);
+        let mut bytes = fs::read(&path).unwrap();
+        let title = b"Prologue";
+        bytes.extend_from_slice(b"chap");
+        bytes.extend_from_slice(&(16 + title.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&(title.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(title);
+        fs::write(&path, &bytes).unwrap();
+        let mut current = FileContainer::open(&path).unwrap();
+
+        bytes[3] = 1;
+        fs::write(&path, &bytes).unwrap();
+        let mut version_1 = FileContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            current.chapters().unwrap(),
+            [Chapter {
+                index: 0,
+                title: String::from("Prologue"),
+            }]
+        );
+        assert_eq!(version_1.chapters().unwrap(), []);
     }
 }
@@ -0,0 +1,63 @@
+use skia_safe::{Color, Paint, Rect};
+
+use crate::text;
+
+/// A key, or combination of keys, and what it does.
+pub type KeyBinding = (&'static str, &'static str);
+
+/// Draws a panel listing `key_bindings` in the middle of the screen.
+pub fn render(
+    key_bindings: &[KeyBinding],
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    const PADDING: f32 = 24.0;
+    const COLUMN_GAP: f32 = 24.0;
+
+    let font = text::font(20.0);
+    let (_, metrics) = font.metrics();
+    let line_height = metrics.descent - metrics.ascent + metrics.leading;
+
+    let column_width = |column: fn(&KeyBinding) -> &str| {
+        key_bindings
+            .iter()
+            .map(|binding| font.measure_str(column(binding), None).0)
+            .fold(0.0, f32::max)
+    };
+    let keys_width = column_width(|(keys, _)| keys);
+    let descriptions_width = column_width(|(_, description)| description);
+
+    let box_width = keys_width + COLUMN_GAP + descriptions_width + 2.0 * PADDING;
+    let box_height = key_bindings.len() as f32 * line_height + 2.0 * PADDING;
+
+    let left = ((screen_width as f32 - box_width) / 2.0).max(0.0);
+    let top = ((screen_height as f32 - box_height) / 2.0).max(0.0);
+
+    let mut paint = Paint::default();
+    paint.set_color(0xDD000000);
+    canvas.draw_round_rect(
+        Rect {
+            left,
+            top,
+            right: left + box_width,
+            bottom: top + box_height,
+        },
+        8.0,
+        8.0,
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    for (i, (keys, description)) in key_bindings.iter().enumerate() {
+        let baseline = top + PADDING - metrics.ascent + i as f32 * line_height;
+        canvas.draw_str(keys, (left + PADDING, baseline), &font, &paint);
+        canvas.draw_str(
+            description,
+            (left + PADDING + keys_width + COLUMN_GAP, baseline),
+            &font,
+            &paint,
+        );
+    }
+}
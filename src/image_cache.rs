@@ -0,0 +1,111 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use gallery_desktop::Frame;
+
+/// The number of bytes of decoded images to keep when no budget is given
+pub const DEFAULT_BUDGET: usize = 512 * 1024 * 1024;
+
+/// Decoded images, keyed by the path of the file they're in and their index in it. It's shared
+/// between screens and threads, and clones refer to the same cache. Once the images take up
+/// more than the budget, the least recently used are dropped.
+#[derive(Clone)]
+pub struct ImageCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    /// The most bytes of decoded images to keep
+    budget: usize,
+    /// The number of bytes taken up by the images in `entries`
+    size: usize,
+    /// From most to least recently used
+    entries: VecDeque<Entry>,
+}
+
+struct Entry {
+    path: PathBuf,
+    index: usize,
+    frames: Vec<Frame>,
+    size: usize,
+}
+
+impl ImageCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                budget,
+                size: 0,
+                entries: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Returns the most bytes of decoded images which are kept.
+    pub fn budget(&self) -> usize {
+        self.inner.lock().unwrap().budget
+    }
+
+    pub fn contains(&self, path: &Path, index: usize) -> bool {
+        let inner = self.inner.lock().unwrap();
+
+        inner.position(path, index).is_some()
+    }
+
+    /// Returns the frames of the image at `index` in the file at `path`, if they're cached.
+    pub fn get(&self, path: &Path, index: usize) -> Option<Vec<Frame>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let i = inner.position(path, index)?;
+        let entry = inner.entries.remove(i)?;
+        let frames = entry.frames.clone();
+        inner.entries.push_front(entry);
+
+        Some(frames)
+    }
+
+    pub fn insert(&self, path: &Path, index: usize, frames: Vec<Frame>) {
+        let size = frames
+            .iter()
+            .map(|frame| frame.image.info.compute_min_byte_size())
+            .sum();
+
+        let mut inner = self.inner.lock().unwrap();
+
+        // Keeping the image would mean dropping everything else, and still being over budget
+        if size > inner.budget {
+            return;
+        }
+
+        if let Some(i) = inner.position(path, index) {
+            let entry = inner.entries.remove(i).unwrap();
+            inner.size -= entry.size;
+        }
+
+        inner.entries.push_front(Entry {
+            path: path.to_path_buf(),
+            index,
+            frames,
+            size,
+        });
+        inner.size += size;
+
+        while inner.size > inner.budget {
+            let Some(entry) = inner.entries.pop_back() else {
+                break;
+            };
+            inner.size -= entry.size;
+        }
+    }
+}
+
+impl Inner {
+    fn position(&self, path: &Path, index: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.index == index && entry.path == path)
+    }
+}
@@ -0,0 +1,14 @@
+use crate::OfcError;
+
+/// A source of encoded images which can be accessed by index.
+pub trait ImageSource {
+    /// Returns the encoded bytes of the image at index `i`, or why they couldn't be read.
+    fn read_at(&mut self, i: usize) -> Result<Vec<u8>, OfcError>;
+
+    /// Returns the number of images in the source.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use winit::event::MouseButton;
+
+use crate::{selector, viewer};
+
+#[derive(Clone, Copy)]
+pub enum SelectorAction {
+    /// Toggles whether the file under the cursor is selected
+    ToggleSelection,
+    PreviousPage,
+    NextPage,
+}
+
+impl SelectorAction {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "toggle-selection" => Self::ToggleSelection,
+            "previous-page" => Self::PreviousPage,
+            "next-page" => Self::NextPage,
+            _ => return None,
+        })
+    }
+
+    pub fn apply(self, screen: &mut selector::Screen, x: f64, y: f64, width: i32, height: i32) {
+        match self {
+            Self::ToggleSelection => screen.on_click(x, y, width, height),
+            Self::PreviousPage => screen.previous_page(),
+            Self::NextPage => screen.next_page(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ViewerAction {
+    NextImage,
+    PreviousImage,
+    NextFile,
+    PreviousFile,
+    ToggleProgress,
+    ToggleLoopWithinFile,
+}
+
+impl ViewerAction {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "next-image" => Self::NextImage,
+            "previous-image" => Self::PreviousImage,
+            "next-file" => Self::NextFile,
+            "previous-file" => Self::PreviousFile,
+            "toggle-progress" => Self::ToggleProgress,
+            "toggle-loop-within-file" => Self::ToggleLoopWithinFile,
+            _ => return None,
+        })
+    }
+
+    pub fn apply(self, screen: &mut viewer::Screen) {
+        match self {
+            Self::NextImage => {
+                screen.next_image();
+            }
+            Self::PreviousImage => {
+                screen.previous_image();
+            }
+            Self::NextFile => screen.next_file(),
+            Self::PreviousFile => screen.previous_file(),
+            Self::ToggleProgress => screen.cycle_progress_display(),
+            Self::ToggleLoopWithinFile => screen.toggle_loop_within_file(),
+        }
+    }
+}
+
+/// The actions performed when a mouse button is pressed, for each type of screen.
+pub struct MouseBindings {
+    selector: HashMap<MouseButton, SelectorAction>,
+    viewer: HashMap<MouseButton, ViewerAction>,
+}
+
+impl Default for MouseBindings {
+    fn default() -> Self {
+        Self {
+            selector: HashMap::from([
+                (MouseButton::Left, SelectorAction::ToggleSelection),
+                (MouseButton::Back, SelectorAction::PreviousPage),
+                (MouseButton::Forward, SelectorAction::NextPage),
+            ]),
+            viewer: HashMap::new(),
+        }
+    }
+}
+
+impl MouseBindings {
+    pub fn selector(&self, button: MouseButton) -> Option<SelectorAction> {
+        self.selector.get(&button).copied()
+    }
+
+    pub fn viewer(&self, button: MouseButton) -> Option<ViewerAction> {
+        self.viewer.get(&button).copied()
+    }
+
+    /// Adds a binding of the form `<screen>:<button>=<action>`, e.g. `viewer:back=previous-image`.
+    /// An action of `none` removes the binding for the button.
+    pub fn bind(&mut self, spec: &str) -> Result<(), String> {
+        let invalid = || format!("invalid mouse binding: {spec}");
+
+        let (screen, binding) = spec.split_once(':').ok_or_else(invalid)?;
+        let (button, action) = binding.split_once('=').ok_or_else(invalid)?;
+        let button = parse_button(button).ok_or_else(invalid)?;
+
+        match screen {
+            "selector" => {
+                if action == "none" {
+                    self.selector.remove(&button);
+                } else {
+                    let action = SelectorAction::parse(action).ok_or_else(invalid)?;
+                    self.selector.insert(button, action);
+                }
+            }
+            "viewer" => {
+                if action == "none" {
+                    self.viewer.remove(&button);
+                } else {
+                    let action = ViewerAction::parse(action).ok_or_else(invalid)?;
+                    self.viewer.insert(button, action);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_button(s: &str) -> Option<MouseButton> {
+    Some(match s {
+        "left" => MouseButton::Left,
+        "right" => MouseButton::Right,
+        "middle" => MouseButton::Middle,
+        "back" => MouseButton::Back,
+        "forward" => MouseButton::Forward,
+        _ => MouseButton::Other(s.parse().ok()?),
+    })
+}
@@ -0,0 +1,26 @@
+//! Reading of ofc containers and decoding of the images stored in them.
+//!
+//! An ofc container starts with the magic bytes `ofc` and a format version byte (currently 2),
+//! followed by the number of images as a little-endian `u32`, a table of little-endian `u64` end
+//! offsets (one per image, relative to the end of the table), and then the encoded images
+//! themselves. From version 1 on, the images are followed by optional [`Metadata`].
+
+pub mod codec;
+pub mod file_container;
+pub mod image_source;
+pub mod mapped_container;
+pub mod metadata;
+pub mod ofc_writer;
+pub mod parallel_read;
+
+pub use codec::{
+    DecodeError, DecodedImage, Frame, ImageFormat, decode_frames, decode_image,
+    decode_image_scaled, decode_image_to_cover, decode_overview, decode_region, encode_thumbnail,
+    image_dimensions, parse_decode_order, pixel_count, set_decode_order, with_decode_order,
+};
+pub use file_container::{CURRENT_VERSION, FileContainer, OfcError};
+pub use image_source::ImageSource;
+pub use mapped_container::MappedContainer;
+pub use metadata::{Chapter, Metadata};
+pub use ofc_writer::OfcWriter;
+pub use parallel_read::{Throughput, read_in_parallel};
@@ -1,12 +1,20 @@
-use std::{env::args_os, ffi::CString, num::NonZeroU32};
+use std::{
+    env::args_os,
+    ffi::CString,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
+use decoder::{Decoded, Decoder};
 use gl::types::GLint;
 use glutin::{
     config::{ConfigTemplateBuilder, GlConfig},
     context::{ContextAttributesBuilder, PossiblyCurrentContext},
     display::{GetGlDisplay, GlDisplay},
+    error::ErrorKind,
     prelude::{GlSurface, NotCurrentGlContext},
-    surface::{Surface as GlutinSurface, SurfaceAttributesBuilder, WindowSurface},
+    surface::{Surface as GlutinSurface, SurfaceAttributesBuilder, SwapInterval, WindowSurface},
 };
 use glutin_winit::DisplayBuilder;
 use raw_window_handle::{HasWindowHandle, RawWindowHandle};
@@ -14,20 +22,39 @@ use skia_safe::{
     Color, ColorType, Surface,
     gpu::{self, SurfaceOrigin, backend_render_targets, gl::FramebufferInfo},
 };
-use state::State;
+use state::{State, WindowMode};
+use window_geometry::{WindowGeometry, with_aspect_ratio};
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalPosition,
-    event::{ElementState, KeyEvent, Modifiers, MouseButton, WindowEvent},
-    event_loop::EventLoop,
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{
+        ElementState, KeyEvent, Modifiers, MouseButton, MouseScrollDelta, StartCause, WindowEvent,
+    },
+    event_loop::{ControlFlow, EventLoop},
     keyboard::{Key, NamedKey},
-    window::{Window, WindowAttributes},
+    window::{Fullscreen, Window, WindowAttributes},
 };
 
-mod file_container;
+mod annotation;
+mod decoder;
+mod help;
+mod image_cache;
+mod input;
+mod preloader;
+mod remote;
+mod scan;
+mod screenshot;
 mod selector;
+mod slideshow_export;
+mod split;
 mod state;
+mod text;
+mod toast;
 mod viewer;
+mod window_geometry;
+
+/// How long the mouse has to be still for before its cursor is hidden, when that's enabled
+const CURSOR_HIDE_DELAY: Duration = Duration::from_secs(2);
 
 fn main() {
     let mut args = args_os();
@@ -39,11 +66,78 @@ fn main() {
         return;
     }
 
-    let el = EventLoop::new().expect("Failed to create event loop");
+    if args[0] == "--split" {
+        let (label, out_dir, path) = match &args[..] {
+            [_, out_dir, path] => (false, out_dir, path),
+            [_, flag, out_dir, path] if flag == "--label-exports" => (true, out_dir, path),
+            _ => {
+                eprintln!("usage: --split [--label-exports] OUT_DIR FILE");
+                return;
+            }
+        };
+
+        if let Err(e) = split::split(Path::new(path), Path::new(out_dir), label) {
+            eprintln!("failed to split {}: {e}", Path::new(path).display());
+        }
+        return;
+    }
+
+    let el = EventLoop::with_user_event()
+        .build()
+        .expect("Failed to create event loop");
+
+    let proxy = el.create_proxy();
+    let decoder = Decoder::new(move |decoded| proxy.send_event(decoded).is_ok());
+    let mut state = match State::new(args, decoder) {
+        Ok(state) => state,
+        Err(message) => {
+            eprintln!("{message}");
+            return;
+        }
+    };
+
+    if let Some(export) = state.slideshow_export.take() {
+        if let Err(e) = export.run() {
+            eprintln!("failed to export the slideshow: {e}");
+        }
+        return;
+    }
+
+    // Windows are opened where they were last closed, unless they're given a size
+    let restored_geometry = match state.window_mode {
+        WindowMode::Windowed { size: None } => WindowGeometry::load(),
+        _ => None,
+    };
+
+    // Windows locked to an aspect ratio keep their width and open at the matching height
+    let lock_aspect_ratio = |size| match state.window_aspect_ratio {
+        Some(ratio) => with_aspect_ratio(size, size, ratio),
+        None => size,
+    };
 
-    let window_attributes = WindowAttributes::default()
-        .with_decorations(false)
-        .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+    let window_attributes = match state.window_mode {
+        WindowMode::Fullscreen => WindowAttributes::default()
+            .with_decorations(false)
+            .with_fullscreen(Some(Fullscreen::Borderless(None))),
+        WindowMode::Windowed { size } => {
+            let window_attributes = WindowAttributes::default().with_title("gallery-desktop");
+            match (size, &restored_geometry) {
+                (Some((width, height)), _) => {
+                    let size = lock_aspect_ratio(PhysicalSize::new(width, height));
+                    window_attributes.with_inner_size(LogicalSize::new(size.width, size.height))
+                }
+                (None, Some(geometry)) => {
+                    let window_attributes =
+                        window_attributes.with_inner_size(lock_aspect_ratio(geometry.size));
+                    match geometry.position {
+                        Some(position) => window_attributes.with_position(position),
+                        None => window_attributes,
+                    }
+                }
+                (None, None) => window_attributes,
+            }
+        }
+    };
 
     let template = ConfigTemplateBuilder::new();
 
@@ -70,6 +164,17 @@ fn main() {
         .unwrap();
 
     let window = window.expect("Could not create window with OpenGL context");
+
+    // The monitors can only be checked once there's a window, so one which was restored out of
+    // sight is moved back onto a monitor afterwards
+    if let Some(geometry) = &restored_geometry
+        && !geometry.is_on_screen(window.available_monitors())
+        && let Some(monitor) = window
+            .primary_monitor()
+            .or_else(|| window.available_monitors().next())
+    {
+        window.set_outer_position(monitor.position());
+    }
     let raw_window_handle = RawWindowHandle::from(
         window
             .window_handle()
@@ -104,6 +209,16 @@ fn main() {
         .make_current(&gl_surface)
         .expect("Could not make GL context current when setting up skia renderer");
 
+    let swap_interval = if state.vsync {
+        SwapInterval::Wait(NonZeroU32::MIN)
+    } else {
+        SwapInterval::DontWait
+    };
+    // Not every platform supports choosing, in which case its default is used
+    if let Err(e) = gl_surface.set_swap_interval(&gl_context, swap_interval) {
+        eprintln!("failed to set vsync: {e}");
+    }
+
     gl::load_with(|s| {
         gl_config
             .display()
@@ -191,9 +306,104 @@ fn main() {
         stencil_size: usize,
         modifiers: Modifiers,
         mouse_position: PhysicalPosition<f64>,
+        /// Whether the left mouse button is held down, to pan the image in the viewer
+        panning: bool,
+        /// When the mouse last moved, for hiding its cursor once it's been still for a while
+        cursor_moved_at: Instant,
+        cursor_hidden: bool,
+        /// Whether to save the next frame that's drawn to a file
+        screenshot_requested: bool,
+        /// Whether screens are laid out in logical pixels and scaled up by the window's scale
+        /// factor, rather than laid out in physical pixels, for checking how they look at other
+        /// scale factors. It can only be turned on with `--debug`.
+        logical_pixels: bool,
+        /// Files dropped onto the window since the last batch of events was handled. They're
+        /// added together so that the viewer moves to the first of them.
+        dropped_files: Vec<PathBuf>,
         state: State,
     }
 
+    impl Application {
+        /// Returns when to hide the cursor, or `None` if it shouldn't be hidden. It's only
+        /// hidden over the viewer, since the selector is used with the mouse.
+        fn cursor_hide_at(&self) -> Option<Instant> {
+            let hides = self.state.hide_idle_cursor
+                && !self.cursor_hidden
+                && matches!(self.state.screen, state::Screen::Viewer(_));
+
+            hides.then(|| self.cursor_moved_at + CURSOR_HIDE_DELAY)
+        }
+
+        /// Returns the size to put the window back to once it's been resized to `size`, if it's
+        /// locked to an aspect ratio which `size` doesn't match. Fullscreen and maximized
+        /// windows are left to fill the space they're given.
+        fn aspect_locked_size(&self, size: PhysicalSize<u32>) -> Option<PhysicalSize<u32>> {
+            let ratio = self.state.window_aspect_ratio?;
+            let window = &self.env.window;
+            if !matches!(self.state.window_mode, WindowMode::Windowed { .. })
+                || window.fullscreen().is_some()
+                || window.is_maximized()
+            {
+                return None;
+            }
+
+            let previous = PhysicalSize::new(
+                self.state.width.try_into().unwrap_or(0),
+                self.state.height.try_into().unwrap_or(0),
+            );
+            let locked = with_aspect_ratio(size, previous, ratio);
+
+            // Sizes only a pixel out are left alone, since the ratio rarely divides exactly
+            (locked.width.abs_diff(size.width) > 1 || locked.height.abs_diff(size.height) > 1)
+                .then_some(locked)
+        }
+
+        /// Returns how many physical pixels each pixel that screens are laid out in takes up.
+        fn render_scale(&self) -> f32 {
+            if self.logical_pixels {
+                self.env.window.scale_factor() as f32
+            } else {
+                1.0
+            }
+        }
+
+        /// Returns the size that screens are laid out in, which is the size of the window in
+        /// logical or physical pixels.
+        fn screen_size(&self) -> (i32, i32) {
+            let scale = self.render_scale();
+
+            (
+                (self.state.width as f32 / scale).round() as i32,
+                (self.state.height as f32 / scale).round() as i32,
+            )
+        }
+
+        /// Returns the position of the mouse in the pixels that screens are laid out in.
+        fn screen_mouse_position(&self) -> (f64, f64) {
+            let scale = f64::from(self.render_scale());
+            let PhysicalPosition { x, y } = self.mouse_position;
+
+            (x / scale, y / scale)
+        }
+
+        /// Wakes the event loop up for the next thing which happens without any input.
+        fn update_control_flow(&self, event_loop: &winit::event_loop::ActiveEventLoop) {
+            let redraw_deadline = match &self.state.screen {
+                state::Screen::Selector(_) => None,
+                state::Screen::Viewer(screen) => screen.redraw_deadline(),
+            };
+            let deadline = redraw_deadline
+                .into_iter()
+                .chain(self.cursor_hide_at())
+                .min();
+
+            event_loop.set_control_flow(match deadline {
+                Some(deadline) => ControlFlow::WaitUntil(deadline),
+                None => ControlFlow::Wait,
+            });
+        }
+    }
+
     let mut application = Application {
         env,
         fb_info,
@@ -201,17 +411,68 @@ fn main() {
         stencil_size,
         modifiers: Modifiers::default(),
         mouse_position: PhysicalPosition { x: 0.0, y: 0.0 },
-        state: State::new(args),
+        panning: false,
+        cursor_moved_at: Instant::now(),
+        cursor_hidden: false,
+        screenshot_requested: false,
+        logical_pixels: false,
+        dropped_files: Vec::new(),
+        state,
     };
 
-    impl ApplicationHandler for Application {
+    impl ApplicationHandler<Decoded> for Application {
         fn resumed(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {}
 
+        fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+            // Made fullscreen with F11, in which case it's left to open as it was before that
+            let window = &self.env.window;
+            if !matches!(self.state.window_mode, WindowMode::Windowed { .. })
+                || window.fullscreen().is_some()
+            {
+                return;
+            }
+
+            let geometry = WindowGeometry {
+                size: window.inner_size(),
+                position: window.outer_position().ok(),
+            };
+            if let Err(e) = geometry.save() {
+                eprintln!("failed to save the window's size and position: {e}");
+            }
+        }
+
+        fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: Decoded) {
+            if let state::Screen::Viewer(screen) = &mut self.state.screen
+                && screen.on_decoded(event)
+            {
+                self.env.window.request_redraw();
+            }
+        }
+
+        fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+            if !self.dropped_files.is_empty() {
+                self.state
+                    .add_files(std::mem::take(&mut self.dropped_files));
+                self.env.window.request_redraw();
+            }
+        }
+
         fn new_events(
             &mut self,
             _event_loop: &winit::event_loop::ActiveEventLoop,
-            _cause: winit::event::StartCause,
+            cause: StartCause,
         ) {
+            if let StartCause::ResumeTimeReached { .. } = cause {
+                if self
+                    .cursor_hide_at()
+                    .is_some_and(|hide_at| Instant::now() >= hide_at)
+                {
+                    self.env.window.set_cursor_visible(false);
+                    self.cursor_hidden = true;
+                }
+
+                self.env.window.request_redraw();
+            }
         }
 
         fn window_event(
@@ -228,6 +489,17 @@ fn main() {
                     return;
                 }
                 WindowEvent::Resized(physical_size) => {
+                    // The window system may apply the size straight away, or report it with
+                    // another resize later. It's drawn at the size it was given until then.
+                    let physical_size = match self.aspect_locked_size(physical_size) {
+                        Some(locked) => self
+                            .env
+                            .window
+                            .request_inner_size(locked)
+                            .unwrap_or(physical_size),
+                        None => physical_size,
+                    };
+
                     self.env.surface = create_surface(
                         &self.env.window,
                         self.fb_info,
@@ -247,7 +519,19 @@ fn main() {
                     self.state.width = i32::try_from(width).unwrap();
                     self.state.height = i32::try_from(height).unwrap();
                 }
+                // Screens laid out in logical pixels change size along with it, even if the
+                // window doesn't
+                WindowEvent::ScaleFactorChanged { .. } if self.logical_pixels => {
+                    self.env.window.request_redraw();
+                }
                 WindowEvent::ModifiersChanged(new_modifiers) => self.modifiers = new_modifiers,
+                WindowEvent::Focused(true) => {
+                    // Modifiers and buttons may have changed while another window had focus,
+                    // without events being delivered here
+                    self.modifiers = Modifiers::default();
+                    self.panning = false;
+                    self.env.window.request_redraw();
+                }
                 WindowEvent::KeyboardInput {
                     event:
                         KeyEvent {
@@ -259,19 +543,106 @@ fn main() {
                         event_loop.exit();
                     }
                     if !state.is_pressed() {
+                        if let state::Screen::Viewer(screen) = &mut self.state.screen
+                            && logical_key == "n"
+                        {
+                            screen.stop_peek();
+                            self.env.window.request_redraw();
+                        }
+                        return;
+                    }
+
+                    if logical_key == "`" {
+                        self.state.toggle_chrome();
+                        self.env.window.request_redraw();
+                        return;
+                    }
+
+                    if logical_key == Key::Named(NamedKey::F11) {
+                        // Resizing the window to or from fullscreen recreates the surface
+                        let window = &self.env.window;
+                        window.set_fullscreen(match window.fullscreen() {
+                            Some(_) => None,
+                            None => Some(Fullscreen::Borderless(None)),
+                        });
+                        return;
+                    }
+
+                    if self.state.debug && logical_key == Key::Named(NamedKey::F12) {
+                        self.logical_pixels = !self.logical_pixels;
+                        eprintln!(
+                            "laying out in {} pixels, at a scale factor of {}",
+                            if self.logical_pixels {
+                                "logical"
+                            } else {
+                                "physical"
+                            },
+                            self.env.window.scale_factor(),
+                        );
+                        self.env.window.request_redraw();
+                        return;
+                    }
+
+                    if logical_key == Key::Named(NamedKey::PrintScreen) {
+                        // The frame is captured as it's drawn, since what's on the surface
+                        // isn't kept after it's presented
+                        self.screenshot_requested = true;
+                        self.env.window.request_redraw();
+                        return;
+                    }
+
+                    if logical_key == "?" {
+                        self.state.toggle_help();
+                        self.env.window.request_redraw();
                         return;
                     }
 
                     match &mut self.state.screen {
-                        state::Screen::Selector(_) => {
-                            if logical_key != Key::Named(NamedKey::Enter) {
+                        state::Screen::Selector(screen) => {
+                            if logical_key == Key::Named(NamedKey::Enter) {
+                                self.state.move_to_viewer();
+                            } else if logical_key == "h" {
+                                screen.focus_left();
+                            } else if logical_key == "l" {
+                                screen.focus_right();
+                            } else if logical_key == "k" {
+                                screen.focus_up();
+                            } else if logical_key == "j" {
+                                screen.focus_down();
+                            } else if logical_key == Key::Named(NamedKey::Space) {
+                                screen.toggle_focused_selection();
+                            } else if logical_key == "t" {
+                                screen.toggle_labels();
+                            } else if logical_key == "a" {
+                                screen.select_all();
+                            } else if logical_key == "A" {
+                                screen.deselect_all();
+                            } else {
                                 return;
                             }
-
-                            self.state.move_to_viewer();
                         }
                         state::Screen::Viewer(screen) => {
-                            if logical_key == "j" {
+                            let digit = match &logical_key {
+                                Key::Character(c) if c.len() == 1 => {
+                                    c.chars().next().filter(char::is_ascii_digit)
+                                }
+                                _ => None,
+                            };
+
+                            // 0 resets the zoom unless it's part of a number being typed
+                            if let Some(digit) = digit
+                                && (digit != '0' || screen.is_typing_number())
+                            {
+                                screen.type_digit(digit);
+                            } else if logical_key == Key::Named(NamedKey::Enter)
+                                && screen.is_typing_number()
+                            {
+                                screen.submit_typed_number();
+                            } else if logical_key == Key::Named(NamedKey::Escape)
+                                && screen.is_typing_number()
+                            {
+                                screen.cancel_typed_number();
+                            } else if logical_key == "j" {
                                 screen.next_image();
                             } else if logical_key == "k" {
                                 screen.previous_image();
@@ -279,8 +650,66 @@ fn main() {
                                 screen.previous_file();
                             } else if logical_key == "h" {
                                 screen.next_file();
+                            } else if logical_key == "}" {
+                                screen.next_chapter();
+                            } else if logical_key == "{" {
+                                screen.previous_chapter();
                             } else if logical_key == "p" {
-                                screen.toggle_progress_display();
+                                screen.cycle_progress_display();
+                            } else if logical_key == "L" {
+                                screen.toggle_loop_within_file();
+                            } else if logical_key == Key::Named(NamedKey::F5) {
+                                screen.retry();
+                            } else if logical_key == "o" {
+                                screen.print_position();
+                            } else if logical_key == "t" {
+                                screen.toggle_captions();
+                            } else if logical_key == "i" {
+                                screen.toggle_info();
+                            } else if logical_key == "d" {
+                                screen.toggle_decoding_indicator();
+                            } else if logical_key == "I" {
+                                screen.toggle_view_state();
+                            } else if logical_key == "n" {
+                                screen.start_peek();
+                            } else if logical_key == "b" {
+                                screen.toggle_sharpen_monochrome();
+                            } else if logical_key == "0" {
+                                screen.reset_zoom();
+                            } else if logical_key == "K" {
+                                screen.toggle_kinetic_panning();
+                            } else if logical_key == "f" {
+                                screen.cycle_fit_mode();
+                            } else if logical_key == Key::Named(NamedKey::Home) {
+                                if self.modifiers.state().shift_key() {
+                                    screen.first_image_overall();
+                                } else {
+                                    screen.first_image();
+                                }
+                            } else if logical_key == Key::Named(NamedKey::End) {
+                                if self.modifiers.state().shift_key() {
+                                    screen.last_image_overall();
+                                } else {
+                                    screen.last_image();
+                                }
+                            } else if logical_key == "w" {
+                                screen.export_png();
+                            } else if logical_key == "c" {
+                                screen.copy_to_clipboard();
+                            } else if logical_key == "x" {
+                                screen.remove_file();
+                            } else if logical_key == "m" {
+                                screen.toggle_mark();
+                            } else if logical_key == "s" {
+                                screen.toggle_slideshow();
+                            } else if logical_key == "[" {
+                                screen.speed_up_slideshow();
+                            } else if logical_key == "]" {
+                                screen.slow_down_slideshow();
+                            } else if logical_key == "r" {
+                                screen.rotate_cw();
+                            } else if logical_key == "R" {
+                                screen.rotate_ccw();
                             } else {
                                 return;
                             }
@@ -289,29 +718,79 @@ fn main() {
 
                     self.env.window.request_redraw();
                 }
+                WindowEvent::DroppedFile(path) => self.dropped_files.push(path),
                 WindowEvent::CursorMoved { position, .. } => {
-                    self.mouse_position = position;
+                    let previous_position = std::mem::replace(&mut self.mouse_position, position);
+
+                    self.cursor_moved_at = Instant::now();
+                    if self.cursor_hidden {
+                        self.env.window.set_cursor_visible(true);
+                        self.cursor_hidden = false;
+                    }
+                    self.update_control_flow(event_loop);
+
+                    let scale = self.render_scale();
+                    if let state::Screen::Viewer(screen) = &mut self.state.screen
+                        && self.panning
+                    {
+                        screen.pan(
+                            (position.x - previous_position.x) as f32 / scale,
+                            (position.y - previous_position.y) as f32 / scale,
+                        );
+                        self.env.window.request_redraw();
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let (x, y) = self.screen_mouse_position();
+                    let (width, height) = self.screen_size();
+                    let state::Screen::Viewer(screen) = &mut self.state.screen else {
+                        return;
+                    };
+
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+                    };
+
+                    screen.zoom_at(1.25_f32.powf(lines), x as f32, y as f32, width, height);
+                    self.env.window.request_redraw();
                 }
                 WindowEvent::MouseInput { state, button, .. } => {
+                    if button == MouseButton::Left {
+                        self.panning = state == ElementState::Pressed;
+
+                        if let state::Screen::Viewer(screen) = &mut self.state.screen {
+                            if self.panning {
+                                screen.start_pan();
+                            } else {
+                                // A flung image is drawn moving from here on
+                                screen.end_pan();
+                                self.env.window.request_redraw();
+                            }
+                        }
+                    }
+
                     if state != ElementState::Pressed {
                         return;
                     }
-                    let state::Screen::Selector(screen) = &mut self.state.screen else {
-                        return;
-                    };
 
-                    match button {
-                        MouseButton::Left => {
-                            let PhysicalPosition { x, y } = self.mouse_position;
-                            screen.on_click(x, y, self.state.width, self.state.height);
-                        }
-                        MouseButton::Back => {
-                            screen.previous_page();
+                    let (x, y) = self.screen_mouse_position();
+                    let (width, height) = self.screen_size();
+                    match &mut self.state.screen {
+                        state::Screen::Selector(screen) => {
+                            let Some(action) = self.state.mouse_bindings.selector(button) else {
+                                return;
+                            };
+
+                            action.apply(screen, x, y, width, height);
                         }
-                        MouseButton::Forward => {
-                            screen.next_page();
+                        state::Screen::Viewer(screen) => {
+                            let Some(action) = self.state.mouse_bindings.viewer(button) else {
+                                return;
+                            };
+
+                            action.apply(screen);
                         }
-                        _ => return,
                     }
 
                     self.env.window.request_redraw();
@@ -323,25 +802,72 @@ fn main() {
             }
 
             if draw_frame {
+                let scale = self.render_scale();
+                let (width, height) = self.screen_size();
                 let canvas = self.env.surface.canvas();
                 canvas.clear(Color::BLACK);
+                canvas.save();
+                canvas.scale((scale, scale));
 
                 match &mut self.state.screen {
-                    state::Screen::Selector(screen) => {
-                        selector::render_frame(self.state.width, self.state.height, screen, canvas)
-                    }
+                    state::Screen::Selector(screen) => selector::render_frame(
+                        width,
+                        height,
+                        screen,
+                        self.state.hide_all_chrome,
+                        canvas,
+                    ),
                     state::Screen::Viewer(screen) => {
-                        viewer::render_frame(self.state.width, self.state.height, screen, canvas);
+                        viewer::render_frame(
+                            width,
+                            height,
+                            screen,
+                            self.state.hide_all_chrome,
+                            &mut self.env.gr_context,
+                            canvas,
+                        );
                     }
                 }
+                if self.state.show_help {
+                    let key_bindings = match &self.state.screen {
+                        state::Screen::Selector(_) => selector::KEY_BINDINGS,
+                        state::Screen::Viewer(_) => viewer::KEY_BINDINGS,
+                    };
+                    help::render(key_bindings, width, height, canvas);
+                }
+                canvas.restore();
+                if std::mem::take(&mut self.screenshot_requested) {
+                    let label = match &self.state.screen {
+                        state::Screen::Selector(_) => None,
+                        state::Screen::Viewer(screen) => screen.export_label(),
+                    };
+                    screenshot::save(&mut self.env.surface, &mut self.env.gr_context, label);
+                }
                 self.env.gr_context.flush_and_submit();
-                self.env
-                    .gl_surface
-                    .swap_buffers(&self.env.gl_context)
-                    .unwrap();
+                if let Err(e) = self.env.gl_surface.swap_buffers(&self.env.gl_context) {
+                    eprintln!("failed to swap buffers: {e}");
+
+                    match e.error_kind() {
+                        // Nothing more can be drawn once the context is gone
+                        ErrorKind::ContextLost | ErrorKind::BadContext => {
+                            event_loop.exit();
+                            return;
+                        }
+                        // Only this frame is dropped. Later ones may succeed.
+                        _ => {}
+                    }
+                }
+
+                self.update_control_flow(event_loop);
             }
         }
     }
 
     el.run_app(&mut application).expect("run() failed");
+
+    if let state::Screen::Viewer(screen) = &application.state.screen
+        && let Err(e) = screen.write_marks()
+    {
+        eprintln!("failed to write the marked images: {e}");
+    }
 }
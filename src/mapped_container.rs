@@ -0,0 +1,107 @@
+use std::{fs::File, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{
+    Chapter, ImageFormat, ImageSource, Metadata,
+    file_container::{OfcError, check_data_len, read_end_offsets},
+};
+
+/// An ofc container on disk which is memory-mapped, so that its images can be borrowed without
+/// being copied.
+pub struct MappedContainer {
+    map: Mmap,
+    /// The version of the format the container is in
+    version: u8,
+    end_offsets: Vec<u64>,
+    /// The order to try decoders in for the container's images, from its metadata, since it's
+    /// needed for every image
+    decode_order: Option<Vec<ImageFormat>>,
+}
+
+impl MappedContainer {
+    /// Maps the container at `p` into memory and reads its offset table.
+    pub fn open(p: &Path) -> Result<Self, OfcError> {
+        let f = File::open(p)?;
+        // SAFETY: Containers are only ever read, so the mapping is only invalidated if another
+        // process truncates the file while it's open, the same as for any other reader of it.
+        let map = unsafe { Mmap::map(&f) }?;
+
+        let (version, end_offsets) = read_end_offsets(&mut &map[..])?;
+        check_data_len(&end_offsets, u64::try_from(map.len()).unwrap())?;
+
+        let mut container = Self {
+            map,
+            version,
+            end_offsets,
+            decode_order: None,
+        };
+        // Containers whose metadata can't be read are decoded like any other
+        container.decode_order = container
+            .metadata()
+            .ok()
+            .and_then(|metadata| metadata.decode_order);
+
+        Ok(container)
+    }
+
+    /// Parses the metadata stored after the images. Containers from before version 1 of the
+    /// format have none.
+    pub fn metadata(&self) -> Result<Metadata, OfcError> {
+        if self.version < 1 {
+            return Ok(Metadata::default());
+        }
+
+        // Checked to be within the map when it was opened, like the offsets of the images
+        let data_len = usize::try_from(self.end_offsets.last().copied().unwrap_or(0)).unwrap();
+        Metadata::parse(&self.map[8 + 8 * self.len() + data_len..], self.version)
+    }
+
+    /// Parses where each chapter of the container starts, leaving out any which start past its
+    /// last image. Containers from before version 2 of the format have none.
+    pub fn chapters(&self) -> Result<Vec<Chapter>, OfcError> {
+        let mut chapters = self.metadata()?.chapters;
+        chapters.retain(|chapter| chapter.index < self.len());
+
+        Ok(chapters)
+    }
+
+    /// Returns the order to try decoders in for the container's images, if it sets one.
+    pub fn decode_order(&self) -> Option<&[ImageFormat]> {
+        self.decode_order.as_deref()
+    }
+
+    /// Returns the encoded bytes of the image at index `i`.
+    pub fn read_at(&self, i: usize) -> &[u8] {
+        assert!(i < self.len());
+
+        // Every offset was checked to be within the map when it was opened, so none of this can
+        // overflow, even on 32-bit targets
+        let data_start = 8 + 8 * self.len();
+        let start = match i {
+            0 => 0,
+            _ => usize::try_from(self.end_offsets[i - 1]).unwrap(),
+        };
+        let end = usize::try_from(self.end_offsets[i]).unwrap();
+
+        &self.map[data_start + start..data_start + end]
+    }
+
+    pub fn len(&self) -> usize {
+        self.end_offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end_offsets.is_empty()
+    }
+}
+
+impl ImageSource for MappedContainer {
+    fn read_at(&mut self, i: usize) -> Result<Vec<u8>, OfcError> {
+        Ok(MappedContainer::read_at(self, i).to_vec())
+    }
+
+    fn len(&self) -> usize {
+        MappedContainer::len(self)
+    }
+}
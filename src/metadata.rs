@@ -0,0 +1,145 @@
+//! Data about a container as a whole, which is stored after its images from version 1 of the
+//! format on. Chapters and decode orders are stored from version 2 on.
+//!
+//! Metadata is a sequence of sections, each made of a 4 byte tag, the length of its contents as
+//! a little-endian `u64`, and then the contents. Sections with tags which aren't known are
+//! skipped, so that new ones can be added without older readers failing on them.
+
+use std::io::{self, Write};
+
+use crate::{ImageFormat, OfcError, parse_decode_order};
+
+/// The tag of the section holding a small encoded image which represents the container
+const THUMBNAIL: [u8; 4] = *b"thmb";
+/// The tag of the section holding the chapters of the container. Each chapter is the index of
+/// its first image as a little-endian `u64`, the length of its title as a little-endian `u64`,
+/// and then the title as UTF-8.
+const CHAPTERS: [u8; 4] = *b"chap";
+/// The tag of the section holding the order to try decoders in for the container's images,
+/// written the same way as for [`parse_decode_order`]
+const DECODE_ORDER: [u8; 4] = *b"dord";
+
+/// The optional data stored about a container. Containers from before version 1 of the format
+/// have none.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// A small encoded copy of the container's first image, for showing in its place without
+    /// decoding it
+    pub thumbnail: Option<Vec<u8>>,
+    /// Where each chapter of the container starts, in order
+    pub chapters: Vec<Chapter>,
+    /// The order to try decoders in for the container's images, in place of the one which is
+    /// set for every container
+    pub decode_order: Option<Vec<ImageFormat>>,
+}
+
+/// A run of images in a container which starts at `index`, and lasts until the next chapter
+/// or the end of the container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub index: usize,
+    pub title: String,
+}
+
+impl Metadata {
+    /// Parses the metadata sections in `bytes`, which run to the end of a container in `version`
+    /// of the format. Sections which weren't part of that version yet are skipped.
+    pub(crate) fn parse(mut bytes: &[u8], version: u8) -> Result<Self, OfcError> {
+        let mut metadata = Self::default();
+
+        while !bytes.is_empty() {
+            let (tag, rest) = bytes
+                .split_first_chunk::<4>()
+                .ok_or(OfcError::InvalidMetadata)?;
+            let (contents, rest) = split_with_len(rest)?;
+
+            if *tag == THUMBNAIL {
+                metadata.thumbnail = Some(contents.to_vec());
+            } else if *tag == CHAPTERS && version >= 2 {
+                metadata.chapters = parse_chapters(contents)?;
+            } else if *tag == DECODE_ORDER && version >= 2 {
+                let order = str::from_utf8(contents)
+                    .ok()
+                    .and_then(parse_decode_order)
+                    .ok_or(OfcError::InvalidMetadata)?;
+                metadata.decode_order = Some(order);
+            }
+
+            bytes = rest;
+        }
+
+        Ok(metadata)
+    }
+
+    /// Writes a section for each piece of metadata which is set.
+    pub(crate) fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        if let Some(thumbnail) = &self.thumbnail {
+            write_section(out, THUMBNAIL, thumbnail)?;
+        }
+
+        if !self.chapters.is_empty() {
+            let mut contents = Vec::new();
+            for Chapter { index, title } in &self.chapters {
+                contents.extend_from_slice(&u64::try_from(*index).unwrap().to_le_bytes());
+                contents.extend_from_slice(&u64::try_from(title.len()).unwrap().to_le_bytes());
+                contents.extend_from_slice(title.as_bytes());
+            }
+            write_section(out, CHAPTERS, &contents)?;
+        }
+
+        if let Some(order) = &self.decode_order {
+            let names: Vec<_> = order.iter().map(|format| format.extension()).collect();
+            write_section(out, DECODE_ORDER, names.join(",").as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the contents of a chapters section, whose chapters have to be in order of the images
+/// they start at.
+fn parse_chapters(mut contents: &[u8]) -> Result<Vec<Chapter>, OfcError> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+
+    while !contents.is_empty() {
+        let (index, rest) = split_u64(contents)?;
+        let (title, rest) = split_with_len(rest)?;
+        let title = String::from_utf8(title.to_vec()).map_err(|_| OfcError::InvalidMetadata)?;
+
+        if chapters.last().is_some_and(|last| last.index >= index) {
+            return Err(OfcError::InvalidMetadata);
+        }
+        chapters.push(Chapter { index, title });
+
+        contents = rest;
+    }
+
+    Ok(chapters)
+}
+
+/// Splits a little-endian `u64` off the start of `bytes`.
+fn split_u64(bytes: &[u8]) -> Result<(usize, &[u8]), OfcError> {
+    let (value, rest) = bytes
+        .split_first_chunk::<8>()
+        .ok_or(OfcError::InvalidMetadata)?;
+    let value =
+        usize::try_from(u64::from_le_bytes(*value)).map_err(|_| OfcError::InvalidMetadata)?;
+
+    Ok((value, rest))
+}
+
+/// Splits a little-endian `u64` length off the start of `bytes`, and then that many bytes.
+fn split_with_len(bytes: &[u8]) -> Result<(&[u8], &[u8]), OfcError> {
+    let (len, rest) = split_u64(bytes)?;
+    if len > rest.len() {
+        return Err(OfcError::InvalidMetadata);
+    }
+
+    Ok(rest.split_at(len))
+}
+
+fn write_section(out: &mut impl Write, tag: [u8; 4], contents: &[u8]) -> io::Result<()> {
+    out.write_all(&tag)?;
+    out.write_all(&u64::try_from(contents.len()).unwrap().to_le_bytes())?;
+    out.write_all(contents)
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Write};
+
+use crate::{
+    Chapter, DecodeError, ImageFormat, Metadata, encode_thumbnail, file_container::CURRENT_VERSION,
+};
+
+/// Builds an ofc container from encoded images, which are written out in the order they're
+/// added.
+#[derive(Default)]
+pub struct OfcWriter {
+    images: Vec<u8>,
+    end_offsets: Vec<u64>,
+    metadata: Metadata,
+}
+
+impl OfcWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_image(&mut self, bytes: &[u8]) {
+        self.images.extend_from_slice(bytes);
+        self.end_offsets
+            .push(u64::try_from(self.images.len()).unwrap());
+    }
+
+    /// Starts a chapter called `title` at the next image to be added.
+    pub fn start_chapter(&mut self, title: &str) {
+        let index = self.end_offsets.len();
+
+        // A chapter with no images is replaced by the one after it
+        if self
+            .metadata
+            .chapters
+            .last()
+            .is_some_and(|last| last.index == index)
+        {
+            self.metadata.chapters.pop();
+        }
+        self.metadata.chapters.push(Chapter {
+            index,
+            title: title.to_string(),
+        });
+    }
+
+    /// Sets the order to try decoders in for the container's images, in place of the one which
+    /// is set for every container when they're read.
+    pub fn set_decode_order(&mut self, order: Vec<ImageFormat>) {
+        self.metadata.decode_order = Some(order);
+    }
+
+    /// Sets the thumbnail of the container, a small encoded copy of its first image which is
+    /// shown in place of decoding the image itself.
+    pub fn set_thumbnail(&mut self, bytes: Vec<u8>) {
+        self.metadata.thumbnail = Some(bytes);
+    }
+
+    /// Makes the thumbnail of the container from the first image added, with its longer side
+    /// at most `max_size` pixels. Containers without images are left without one.
+    pub fn generate_thumbnail(&mut self, max_size: i32) -> Result<(), DecodeError> {
+        let Some(&first_end) = self.end_offsets.first() else {
+            return Ok(());
+        };
+
+        let first_image = &self.images[..usize::try_from(first_end).unwrap()];
+        self.set_thumbnail(encode_thumbnail(first_image, max_size)?);
+
+        Ok(())
+    }
+
+    /// Writes the header, the offset table, the images added so far, and the metadata to
+    /// `out`.
+    pub fn finish(self, out: &mut impl Write) -> io::Result<()> {
+        let num_files = u32::try_from(self.end_offsets.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many images"))?;
+
+        out.write_all(b"ofc")?;
+        out.write_all(&[CURRENT_VERSION])?;
+        out.write_all(&num_files.to_le_bytes())?;
+        for end_offset in self.end_offsets {
+            out.write_all(&end_offset.to_le_bytes())?;
+        }
+        out.write_all(&self.images)?;
+        self.metadata.write(out)?;
+
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+    use crate::{FileContainer, MappedContainer};
+
+    fn write(name: &str, images: &[&[u8]]) -> PathBuf {
+        let mut writer = OfcWriter::new();
+        for image in images {
+            writer.add_image(image);
+        }
+
+        let path = env::temp_dir().join(format!("gallery-desktop-{name}-{}.ofc", process::id()));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+        path
+    }
+
+    fn read_all(file: &mut FileContainer) -> Vec<Vec<u8>> {
+        (0..file.len()).map(|i| file.read_at(i).unwrap()).collect()
+    }
+
+    #[test]
+    fn written_images_read_back_in_order() {
+        let images: &[&[u8]] = &[b"first", b"", b"third image"];
+        let path = write("round-trip", images);
+
+        let mut eager = FileContainer::open(&path).unwrap();
+        let mut lazy = FileContainer::open_lazy(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(read_all(&mut eager), images);
+        assert_eq!(read_all(&mut lazy), images);
+    }
+
+    #[test]
+    fn thumbnails_read_back_from_after_the_images() {
+        let mut writer = OfcWriter::new();
+        writer.add_image(b"image");
+        writer.set_thumbnail(b"thumbnail".to_vec());
+
+        let path = env::temp_dir().join(format!("gallery-desktop-thumbnail-{}.ofc", process::id()));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut file = FileContainer::open_lazy(&path).unwrap();
+        let mapped = MappedContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(read_all(&mut file), [b"image"]);
+        for metadata in [file.metadata().unwrap(), mapped.metadata().unwrap()] {
+            assert_eq!(metadata.thumbnail.as_deref(), Some(&b"thumbnail"[..]));
+        }
+    }
+
+    #[test]
+    fn chapters_start_at_the_next_image_added() {
+        let mut writer = OfcWriter::new();
+        writer.start_chapter("Empty");
+        writer.start_chapter("One");
+        writer.add_image(b"first");
+        writer.add_image(b"second");
+        writer.start_chapter("Two");
+        writer.add_image(b"third");
+        writer.start_chapter("Past the end");
+
+        let path = env::temp_dir().join(format!("gallery-desktop-chapters-{}.ofc", process::id()));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut file = FileContainer::open_lazy(&path).unwrap();
+        let mapped = MappedContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let chapters = [
+            Chapter {
+                index: 0,
+                title: String::from("One"),
+            },
+            Chapter {
+                index: 2,
+                title: String::from("Two"),
+            },
+        ];
+        assert_eq!(file.chapters().unwrap(), chapters);
+        assert_eq!(mapped.chapters().unwrap(), chapters);
+    }
+
+    #[test]
+    fn decode_orders_read_back_from_the_metadata() {
+        let mut writer = OfcWriter::new();
+        writer.add_image(b"image");
+        writer.set_decode_order(vec![ImageFormat::Png, ImageFormat::Jpeg]);
+
+        let path = env::temp_dir().join(format!(
+            "gallery-desktop-decode-order-{}.ofc",
+            process::id()
+        ));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut file = FileContainer::open_lazy(&path).unwrap();
+        let mapped = MappedContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let order = [ImageFormat::Png, ImageFormat::Jpeg];
+        assert_eq!(file.decode_order().as_deref(), Some(&order[..]));
+        assert_eq!(mapped.decode_order(), Some(&order[..]));
+    }
+
+    #[test]
+    fn containers_without_images_read_back_empty() {
+        let path = write("round-trip-empty", &[]);
+
+        let eager = FileContainer::open(&path).unwrap();
+        let lazy = FileContainer::open_lazy(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(eager.is_empty());
+        assert!(lazy.is_empty());
+    }
+}
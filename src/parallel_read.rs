@@ -0,0 +1,148 @@
+use std::{
+    fmt,
+    num::NonZeroUsize,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::MappedContainer;
+
+/// How much was read by [`read_in_parallel`], and how long it took.
+#[derive(Debug)]
+pub struct Throughput {
+    pub images: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Avoids dividing by zero for containers which are processed too quickly to measure
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let megabytes = self.bytes as f64 / (1024.0 * 1024.0);
+
+        write!(
+            f,
+            "{} images, {megabytes:.1} MiB in {seconds:.2}s ({:.1} MiB/s, {:.1} images/s)",
+            self.images,
+            megabytes / seconds,
+            self.images as f64 / seconds,
+        )
+    }
+}
+
+/// Calls `f` with the index and encoded bytes of every image in `file`, on a thread per core.
+/// Images are handed out in order, but finish in any order. Once `f` fails, no more images are
+/// started, and the first error is returned after the ones in progress finish.
+pub fn read_in_parallel<E: Send>(
+    file: &MappedContainer,
+    f: impl Fn(usize, &[u8]) -> Result<(), E> + Sync,
+) -> Result<Throughput, E> {
+    let started_at = Instant::now();
+
+    // The map is shared, so unlike with a `FileContainer`, threads don't each need their own
+    // handle to the file to read from it
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(file.len());
+    let next_index = AtomicUsize::new(0);
+    let bytes = AtomicU64::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let i = next_index.fetch_add(1, Ordering::Relaxed);
+                    if i >= file.len() {
+                        break;
+                    }
+
+                    let image = file.read_at(i);
+                    match f(i, image) {
+                        Ok(()) => {
+                            bytes.fetch_add(u64::try_from(image.len()).unwrap(), Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            failed.store(true, Ordering::Relaxed);
+                            first_error.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = first_error.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    Ok(Throughput {
+        images: file.len(),
+        bytes: bytes.into_inner(),
+        elapsed: started_at.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::OfcWriter;
+
+    #[test]
+    fn every_image_is_read_once() {
+        let mut writer = OfcWriter::new();
+        for image in 0..100u8 {
+            writer.add_image(&[image; 3]);
+        }
+
+        let path = env::temp_dir().join(format!("gallery-desktop-parallel-{}.ofc", process::id()));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+        let file = MappedContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let read = Mutex::new(Vec::new());
+        let throughput = read_in_parallel(&file, |i, bytes| {
+            read.lock().unwrap().push((i, bytes.to_vec()));
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+
+        let mut read = read.into_inner().unwrap();
+        read.sort();
+        let expected: Vec<_> = (0..100u8).map(|i| (usize::from(i), vec![i; 3])).collect();
+        assert_eq!(read, expected);
+        assert_eq!((throughput.images, throughput.bytes), (100, 300));
+    }
+
+    #[test]
+    fn reading_stops_at_the_first_error() {
+        let mut writer = OfcWriter::new();
+        for image in 0..100u8 {
+            writer.add_image(&[image]);
+        }
+
+        let path = env::temp_dir().join(format!(
+            "gallery-desktop-parallel-error-{}.ofc",
+            process::id()
+        ));
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+        let file = MappedContainer::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let result = read_in_parallel(&file, |i, _| if i == 0 { Err(i) } else { Ok(()) });
+
+        assert!(matches!(result, Err(0)));
+    }
+}
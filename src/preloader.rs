@@ -0,0 +1,163 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use gallery_desktop::{
+    MappedContainer, decode_frames, image_dimensions, pixel_count, with_decode_order,
+};
+
+use crate::image_cache::ImageCache;
+
+/// The number of bytes each pixel of a decoded image takes up
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// Decodes images on a background thread ahead of them being displayed, adding them to a cache.
+pub struct Preloader {
+    requests: Sender<Request>,
+    /// The number of requests which haven't been worked through yet
+    outstanding: Arc<AtomicUsize>,
+}
+
+struct Request {
+    images: Vec<(PathBuf, usize)>,
+    /// Images to decode afterwards, for as long as they fit in the cache together and no newer
+    /// request is waiting
+    spare: Vec<(PathBuf, usize)>,
+}
+
+impl Preloader {
+    /// Starts the decoding thread. Images with more pixels than `tile_above_pixels` are left
+    /// out, since they're too large to decode whole.
+    pub fn new(cache: ImageCache, tile_above_pixels: Option<u64>) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let outstanding = Arc::new(AtomicUsize::new(0));
+        let worker_outstanding = outstanding.clone();
+        thread::spawn(move || preload(receiver, cache, tile_above_pixels, worker_outstanding));
+
+        Self {
+            requests,
+            outstanding,
+        }
+    }
+
+    /// Starts decoding the image at each index, in the file at the given path, replacing any
+    /// earlier request which hasn't been worked on yet. The `spare` images are decoded after
+    /// them, until they'd take up more than the cache's budget between them.
+    pub fn preload(&self, images: Vec<(PathBuf, usize)>, spare: Vec<(PathBuf, usize)>) {
+        // Counted before it's sent, so that the worker can't finish it first
+        self.outstanding.fetch_add(1, Ordering::SeqCst);
+
+        // This only fails if the worker panicked, in which case images are decoded on demand
+        if self.requests.send(Request { images, spare }).is_err() {
+            self.outstanding.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns whether images are still being preloaded.
+    pub fn is_busy(&self) -> bool {
+        self.outstanding.load(Ordering::SeqCst) > 0
+    }
+}
+
+fn preload(
+    receiver: Receiver<Request>,
+    cache: ImageCache,
+    tile_above_pixels: Option<u64>,
+    outstanding: Arc<AtomicUsize>,
+) {
+    let mut open_file: Option<(PathBuf, MappedContainer)> = None;
+
+    // This stops once the `Preloader` is dropped
+    while let Ok(mut request) = receiver.recv() {
+        let mut received = 1;
+        // Navigation has moved on from all but the latest request
+        while let Ok(newer_request) = receiver.try_recv() {
+            request = newer_request;
+            received += 1;
+        }
+
+        for (path, index) in request.images {
+            if cache.contains(&path, index) {
+                continue;
+            }
+
+            // Images which can't be read or decoded are left for the viewer to report, and ones
+            // which are too large to decode whole for it to decode an overview of
+            let Some((file, bytes)) = read(&mut open_file, path.clone(), index) else {
+                continue;
+            };
+            let frames = with_decode_order(file.decode_order(), || {
+                if tile_above_pixels.is_some_and(|max_pixels| {
+                    image_dimensions(bytes).is_ok_and(|size| pixel_count(size) > max_pixels)
+                }) {
+                    return None;
+                }
+                decode_frames(bytes).ok()
+            });
+            if let Some(frames) = frames {
+                cache.insert(&path, index, frames);
+            }
+        }
+
+        // Spare images would push out the ones navigation is heading towards if they didn't all
+        // fit, so they're given up on as soon as they wouldn't
+        let mut spare_bytes = 0;
+        for (path, index) in request.spare {
+            if outstanding.load(Ordering::SeqCst) > received {
+                break;
+            }
+            if cache.contains(&path, index) {
+                continue;
+            }
+
+            let Some((file, bytes)) = read(&mut open_file, path.clone(), index) else {
+                continue;
+            };
+            let decode_order = file.decode_order();
+            let Ok(size) = with_decode_order(decode_order, || image_dimensions(bytes)) else {
+                continue;
+            };
+            if tile_above_pixels.is_some_and(|max_pixels| pixel_count(size) > max_pixels) {
+                continue;
+            }
+
+            spare_bytes += pixel_count(size) * BYTES_PER_PIXEL;
+            if spare_bytes > cache.budget() as u64 {
+                break;
+            }
+
+            if let Ok(frames) = with_decode_order(decode_order, || decode_frames(bytes)) {
+                cache.insert(&path, index, frames);
+            }
+        }
+
+        outstanding.fetch_sub(received, Ordering::SeqCst);
+    }
+}
+
+/// Returns the file at `path` along with its encoded image at `index`, borrowed from the file's
+/// mapping. The file is kept mapped for reading the next image from, since that's usually in the
+/// same file.
+fn read(
+    open_file: &mut Option<(PathBuf, MappedContainer)>,
+    path: PathBuf,
+    index: usize,
+) -> Option<(&MappedContainer, &[u8])> {
+    if open_file
+        .as_ref()
+        .is_none_or(|(open_path, _)| *open_path != path)
+    {
+        // Files which can't be opened are left for the viewer to report
+        let file = MappedContainer::open(&path).ok()?;
+        *open_file = Some((path, file));
+    }
+    let (_, file) = open_file.as_ref()?;
+
+    (index < file.len()).then(|| (file, file.read_at(index)))
+}
@@ -0,0 +1,91 @@
+use std::{ffi::OsStr, fs, path::PathBuf};
+
+#[cfg(feature = "http")]
+use gallery_desktop::{ImageFormat, OfcWriter};
+
+/// The largest file which is downloaded, since downloads are held in memory before being saved
+#[cfg(feature = "http")]
+const MAX_DOWNLOAD_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// The directory in the temporary directory which downloads are saved to. It's removed along
+/// with them once this is dropped, when the app exits.
+#[derive(Default)]
+pub struct Downloads {
+    /// The directory, once something has been downloaded to it
+    dir: Option<PathBuf>,
+}
+
+impl Drop for Downloads {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir
+            && let Err(e) = fs::remove_dir_all(dir)
+        {
+            eprintln!("failed to remove {}: {e}", dir.display());
+        }
+    }
+}
+
+/// Returns whether a command line argument names a remote file rather than a local path.
+pub fn is_url(arg: &OsStr) -> bool {
+    let arg = arg.as_encoded_bytes();
+    arg.starts_with(b"http://") || arg.starts_with(b"https://")
+}
+
+/// Downloads the ofc file or image at `url`, and returns the path to a local ofc file with its
+/// contents. Screens reopen files by path as they're navigated, so the download is staged in
+/// `downloads` instead of being held in memory. A single image is stored as a container with
+/// one entry. Files over [`MAX_DOWNLOAD_BYTES`] are refused.
+#[cfg(feature = "http")]
+pub fn fetch(url: &str, downloads: &mut Downloads) -> Result<PathBuf, String> {
+    let bytes = ureq::get(url)
+        .call()
+        .and_then(|mut response| {
+            response
+                .body_mut()
+                .with_config()
+                .limit(MAX_DOWNLOAD_BYTES)
+                .read_to_vec()
+        })
+        .map_err(|e| format!("couldn't fetch {url}: {e}"))?;
+
+    let contents = if bytes.starts_with(b"ofc") {
+        bytes
+    } else if ImageFormat::detect(&bytes).is_some() {
+        let mut writer = OfcWriter::new();
+        writer.add_image(&bytes);
+
+        // Writing to memory can't fail
+        let mut contents = Vec::new();
+        writer.finish(&mut contents).unwrap();
+        contents
+    } else {
+        return Err(format!("{url} isn't an ofc file or a supported image"));
+    };
+
+    let name = url
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("download");
+    let dir = match &downloads.dir {
+        Some(dir) => dir,
+        None => {
+            let dir = std::env::temp_dir().join(format!("gallery-desktop-{}", std::process::id()));
+            fs::create_dir_all(&dir).map_err(|e| format!("couldn't save {url}: {e}"))?;
+            downloads.dir.insert(dir)
+        }
+    };
+
+    // Prefix with a counter so that URLs ending in the same name don't overwrite each other
+    let count = fs::read_dir(dir).map_or(0, |entries| entries.count());
+    let path = dir.join(format!("{count}-{name}"));
+    fs::write(&path, contents).map_err(|e| format!("couldn't save {url}: {e}"))?;
+
+    Ok(path)
+}
+
+#[cfg(not(feature = "http"))]
+pub fn fetch(url: &str, _downloads: &mut Downloads) -> Result<PathBuf, String> {
+    Err(format!(
+        "can't open {url}: built without support for URLs (enable the http feature)"
+    ))
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Returns the ofc files in `dir`, sorted by path, including the ones in its subdirectories if
+/// `recursive` is set. Other files are left out.
+pub fn ofc_files(dir: &Path, recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_ofc_files(dir, recursive, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+fn collect_ofc_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        // Symlinks to directories aren't followed, so that cycles can't be walked forever
+        if entry.file_type()?.is_dir() {
+            if recursive && let Err(e) = collect_ofc_files(&path, recursive, files) {
+                eprintln!("skipping {}: {e}", path.display());
+            }
+        } else if is_ofc(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether the file at `path` starts with the magic bytes of an ofc file.
+fn is_ofc(path: &Path) -> bool {
+    let mut magic = [0; 3];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == b"ofc"
+}
@@ -0,0 +1,44 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use skia_safe::{EncodedImageFormat, Surface, gpu::DirectContext};
+
+use crate::annotation;
+
+/// Saves what's drawn on `surface` to a PNG file in the working directory, named after the
+/// time, with `label` drawn onto it if there is one. The pixels are read back right away, then
+/// encoded and written in the background, and the outcome is reported on stderr.
+pub fn save(surface: &mut Surface, context: &mut DirectContext, label: Option<String>) {
+    let Some(image) = surface.image_snapshot().make_raster_image(context, None) else {
+        eprintln!("failed to read back the screen");
+        return;
+    };
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis());
+    let path = PathBuf::from(format!("screenshot-{millis}.png"));
+
+    thread::spawn(move || {
+        let result = match label {
+            Some(label) => annotation::label_image(&image, &label)
+                .ok_or_else(|| io::Error::other("couldn't draw the label on the screen")),
+            None => Ok(image),
+        }
+        .and_then(|image| {
+            image
+                .encode(None, EncodedImageFormat::PNG, None)
+                .ok_or_else(|| io::Error::other("couldn't encode the screen as PNG"))
+        })
+        .and_then(|data| fs::write(&path, data.as_bytes()));
+
+        match result {
+            Ok(()) => eprintln!("wrote {}", path.display()),
+            Err(e) => eprintln!("failed to write {}: {e}", path.display()),
+        }
+    });
+}
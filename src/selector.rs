@@ -1,25 +1,97 @@
 use std::{
-    fs::File,
-    io::{Cursor, Read, Seek},
+    collections::HashMap,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
 };
 
-use skia_safe::{
-    codec::{jpeg_decoder, png_decoder, webp_decoder},
-    ISize, Image, ImageInfo, Paint, Rect, SamplingOptions,
+use gallery_desktop::{
+    DecodedImage, FileContainer, decode_image, decode_image_to_cover, with_decode_order,
 };
-
-pub const NUM_COLUMNS: i32 = 4;
-pub const NUM_ROWS: i32 = 3;
+use skia_safe::{ClipOp, Color, ISize, ImageInfo, Paint, PaintStyle, Rect, SamplingOptions};
+
+use crate::{help::KeyBinding, image_cache::ImageCache, text};
+
+/// The size of the grid of covers on each page, unless another size is configured
+pub const DEFAULT_COLUMNS: i32 = 4;
+pub const DEFAULT_ROWS: i32 = 3;
+/// The most columns or rows the grid can have. Every cover on a page is decoded when it's shown,
+/// so larger grids would mostly be slow to page through.
+pub const MAX_GRID_SIZE: i32 = 32;
+
+/// The smallest width or height, in pixels, of a cell in the grid to draw a cover in
+const MIN_CELL_SIZE: i32 = 8;
+
+/// The size of the font and the space around each line for the names of files under covers
+const LABEL_FONT_SIZE: f32 = 16.0;
+const LABEL_PADDING: f32 = 4.0;
+
+/// The keys which can be used on the selector, for the help overlay
+pub const KEY_BINDINGS: &[KeyBinding] = &[
+    ("Enter", "View the selected files"),
+    ("h / j / k / l", "Move between files"),
+    ("Space", "Select or deselect the file"),
+    ("a / A", "Select or deselect every file"),
+    ("t", "Show file names"),
+    ("F11", "Toggle fullscreen"),
+    ("Print Screen", "Save a screenshot"),
+    ("`", "Hide overlays"),
+    ("?", "Show or hide this help"),
+    ("Super+Q", "Quit"),
+];
+
+/// The width, in pixels, of the border drawn around the focused cell
+const FOCUS_BORDER_WIDTH: f32 = 4.0;
 
 pub struct Screen {
     pub ofcs: Vec<Ofc>,
     pub page_index: usize,
+    /// The width / height ratio to crop every cover to, for a uniform grid
+    pub cover_aspect_ratio: Option<f32>,
+    pub cover: Cover,
+    /// The number of columns and rows in the grid of covers on each page
+    pub columns: i32,
+    pub rows: i32,
+    /// Whether to show the name of each file beneath its cover
+    show_labels: bool,
+    /// The index into `ofcs` of the cell which the keyboard acts on, or `None` if the keyboard
+    /// hasn't been used to move between cells yet
+    focused: Option<usize>,
+    /// The cover of each file which has been shown. Covers don't change during a session, so
+    /// they're kept for flipping back to a page.
+    covers: HashMap<PathBuf, Thumbnail>,
+    cache: ImageCache,
+}
+
+/// How to choose which image in a file to use as its cover
+#[derive(Clone, Copy, Default)]
+pub enum Cover {
+    #[default]
+    First,
+    Middle,
+    /// The image with the most encoded bytes, which is usually the one with the largest area
+    Largest,
+    /// The image at a fixed index, or the last image if the file is too short
+    Index(usize),
+}
+
+impl Cover {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "first" => Self::First,
+            "middle" => Self::Middle,
+            "largest" => Self::Largest,
+            _ => Self::Index(s.parse().ok()?),
+        })
+    }
 }
 
 impl Screen {
-    pub fn new(paths: Vec<PathBuf>) -> Self {
+    pub fn new(paths: Vec<PathBuf>, cache: ImageCache) -> Self {
         Self {
             ofcs: paths
                 .into_iter()
@@ -29,40 +101,182 @@ impl Screen {
                 })
                 .collect(),
             page_index: 0,
+            cover_aspect_ratio: None,
+            cover: Cover::default(),
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            show_labels: false,
+            focused: None,
+            covers: HashMap::new(),
+            cache,
         }
     }
 
+    /// Adds files after the ones already in the grid, unselected.
+    pub fn add_files(&mut self, paths: Vec<PathBuf>) {
+        self.ofcs.extend(paths.into_iter().map(|path| Ofc {
+            path,
+            selected: false,
+        }));
+    }
+
     pub fn previous_page(&mut self) {
         self.page_index = self.page_index.saturating_sub(1);
+        self.keep_focus_on_page();
     }
 
     pub fn next_page(&mut self) {
         self.page_index = (self.page_index + 1).min(self.page_count() - 1);
+        self.keep_focus_on_page();
+    }
+
+    /// Moves the focus to the same cell on the current page, or its last cell if the page is
+    /// too short, after changing pages.
+    fn keep_focus_on_page(&mut self) {
+        if let Some(focused) = self.focused {
+            let index = self.page_index * self.page_size() + focused % self.page_size();
+            self.focused = Some(index.min(self.ofcs.len() - 1));
+        }
+    }
+
+    pub fn focus_left(&mut self) {
+        self.move_focus(-1, 0);
+    }
+
+    pub fn focus_right(&mut self) {
+        self.move_focus(1, 0);
+    }
+
+    pub fn focus_up(&mut self) {
+        self.move_focus(0, -1);
+    }
+
+    pub fn focus_down(&mut self) {
+        self.move_focus(0, 1);
+    }
+
+    /// Moves the focus by `dx` columns and `dy` rows. Moving past an edge of the grid moves to
+    /// the opposite edge of the previous or next page.
+    fn move_focus(&mut self, dx: i32, dy: i32) {
+        let page_size = self.page_size();
+
+        // The first move only shows where the focus starts
+        let Some(focused) = self.focused else {
+            self.focused = Some(self.page_index * page_size);
+            return;
+        };
+
+        let index_in_page = i32::try_from(focused % page_size).unwrap();
+        let mut page_index = i32::try_from(self.page_index).unwrap();
+        let mut col = index_in_page % self.columns + dx;
+        let mut row = index_in_page / self.columns + dy;
+
+        if col < 0 {
+            col = self.columns - 1;
+            page_index -= 1;
+        } else if col >= self.columns {
+            col = 0;
+            page_index += 1;
+        }
+        if row < 0 {
+            row = self.rows - 1;
+            page_index -= 1;
+        } else if row >= self.rows {
+            row = 0;
+            page_index += 1;
+        }
+
+        let Ok(page_index) = usize::try_from(page_index) else {
+            return;
+        };
+        if page_index >= self.page_count() {
+            return;
+        }
+
+        let index_in_page = usize::try_from(row * self.columns + col).unwrap();
+        self.page_index = page_index;
+        self.focused = Some((page_index * page_size + index_in_page).min(self.ofcs.len() - 1));
+    }
+
+    pub fn toggle_labels(&mut self) {
+        self.show_labels = !self.show_labels;
+    }
+
+    /// Selects every file, on every page.
+    pub fn select_all(&mut self) {
+        self.set_all_selected(true);
+    }
+
+    /// Deselects every file, on every page.
+    pub fn deselect_all(&mut self) {
+        self.set_all_selected(false);
+    }
+
+    fn set_all_selected(&mut self, selected: bool) {
+        for ofc in &mut self.ofcs {
+            ofc.selected = selected;
+        }
+    }
+
+    /// Toggles whether the file in the focused cell is selected.
+    pub fn toggle_focused_selection(&mut self) {
+        if let Some(ofc) = self.focused.and_then(|focused| self.ofcs.get_mut(focused)) {
+            ofc.selected = !ofc.selected;
+        }
     }
 
     fn current_page(&self) -> &[Ofc] {
-        let start = &self.ofcs[self.page_index * Self::page_size()..];
-        &start[..Self::page_size().min(start.len())]
+        let start = &self.ofcs[self.page_index * self.page_size()..];
+        &start[..self.page_size().min(start.len())]
+    }
+
+    /// Returns the covers of the files on the current page for cells of `cell_size`, decoding
+    /// the ones which haven't been shown at that size or larger before.
+    fn current_page_covers(&mut self, cell_size: ISize) -> Vec<Option<DecodedImage>> {
+        let missing: Vec<PathBuf> = self
+            .current_page()
+            .iter()
+            .filter(|ofc| {
+                self.covers.get(&ofc.path).is_none_or(|thumbnail| {
+                    thumbnail.cell_size.width < cell_size.width
+                        || thumbnail.cell_size.height < cell_size.height
+                })
+            })
+            .map(|ofc| ofc.path.clone())
+            .collect();
+        let decoded = decode_images(&missing, self.cover, &self.cache, cell_size);
+        self.covers.extend(
+            missing
+                .into_iter()
+                .zip(decoded)
+                .map(|(path, image)| (path, Thumbnail { image, cell_size })),
+        );
+
+        self.current_page()
+            .iter()
+            .map(|ofc| self.covers[&ofc.path].image.clone())
+            .collect()
     }
 
     fn page_count(&self) -> usize {
-        self.ofcs.len().div_ceil(Self::page_size())
+        self.ofcs.len().div_ceil(self.page_size())
     }
 
-    fn page_size() -> usize {
-        usize::try_from(NUM_COLUMNS * NUM_ROWS).unwrap()
+    fn page_size(&self) -> usize {
+        usize::try_from(self.columns * self.rows).unwrap()
     }
 
     pub fn on_click(&mut self, x: f64, y: f64, width: i32, height: i32) {
-        let col = x / width as f64 * NUM_COLUMNS as f64;
-        let col = col.floor() as usize;
-
-        let row = y / height as f64 * NUM_ROWS as f64;
-        let row = row.floor() as usize;
+        // Cells are the same size as they're drawn at, which can leave a gap at the right and
+        // bottom edges of the screen
+        let col = (x / (width / self.columns) as f64).floor() as i32;
+        let row = (y / (height / self.rows) as f64).floor() as i32;
+        if !(0..self.columns).contains(&col) || !(0..self.rows).contains(&row) {
+            return;
+        }
 
-        let index_in_page = row * usize::try_from(NUM_COLUMNS).unwrap() + col;
-        let index =
-            usize::try_from(NUM_ROWS * NUM_COLUMNS).unwrap() * self.page_index + index_in_page;
+        let index_in_page = usize::try_from(row * self.columns + col).unwrap();
+        let index = self.page_size() * self.page_index + index_in_page;
 
         if let Some(ofc) = self.ofcs.get_mut(index) {
             ofc.selected = !ofc.selected;
@@ -70,6 +284,14 @@ impl Screen {
     }
 }
 
+/// A cover decoded at about the size of the cells it's drawn in.
+struct Thumbnail {
+    /// The decoded cover, or `None` if the file has no cover which can be decoded
+    image: Option<DecodedImage>,
+    /// The size of the cells which the cover was decoded for
+    cell_size: ISize,
+}
+
 pub struct Ofc {
     pub path: PathBuf,
     pub selected: bool,
@@ -87,32 +309,82 @@ pub fn render_frame(
     screen_width: i32,
     screen_height: i32,
     state: &mut Screen,
+    hide_chrome: bool,
     canvas: &skia_safe::Canvas,
 ) {
-    let max_width: i32 = screen_width / NUM_COLUMNS;
-    let max_height: i32 = screen_height / NUM_ROWS;
+    let Some(cell_size) = cell_size(screen_width, screen_height, state.columns, state.rows) else {
+        return;
+    };
+    let ISize {
+        width: max_width,
+        height: max_height,
+    } = cell_size;
 
+    let decoded_images = state.current_page_covers(cell_size);
     let ofcs = state.current_page();
-    let decoded_images = decode_images(ofcs);
 
     let mut paint = Paint::default();
     paint.set_color(0xAA000000);
 
+    let mut focus_paint = Paint::default();
+    focus_paint.set_color(Color::WHITE);
+    focus_paint.set_style(PaintStyle::Stroke);
+    focus_paint.set_stroke_width(FOCUS_BORDER_WIDTH);
+
+    let focused_in_page = state
+        .focused
+        .and_then(|focused| focused.checked_sub(state.page_index * state.page_size()));
+
+    let label_font = text::font(LABEL_FONT_SIZE);
+    let (_, label_metrics) = label_font.metrics();
+    let show_labels = state.show_labels && !hide_chrome;
+    // Covers are shrunk to leave room for their labels at the bottom of their cells
+    let label_height = if show_labels {
+        (label_metrics.descent - label_metrics.ascent + 2.0 * LABEL_PADDING).ceil() as i32
+    } else {
+        0
+    };
+    let cover_height = max_height - label_height;
+
     for (i, decoded_image) in decoded_images.into_iter().enumerate() {
         let i = i32::try_from(i).unwrap();
-        let x_offset = (i % NUM_COLUMNS) * max_width;
-        let y_offset = (i / NUM_COLUMNS) * max_height;
-
-        draw_cover(
-            canvas,
-            decoded_image,
-            x_offset,
-            y_offset,
-            max_width,
-            max_height,
-        );
+        let x_offset = (i % state.columns) * max_width;
+        let y_offset = (i / state.columns) * max_height;
+
+        match decoded_image {
+            Some(decoded_image) => draw_cover(
+                canvas,
+                decoded_image,
+                x_offset,
+                y_offset,
+                max_width,
+                cover_height,
+                state.cover_aspect_ratio,
+            ),
+            None => draw_placeholder(canvas, x_offset, y_offset, max_width, cover_height),
+        }
+
+        if show_labels {
+            let ofc = &ofcs[usize::try_from(i).unwrap()];
+            let name = ofc.path.file_stem().unwrap_or_default().to_string_lossy();
+            let label = text::truncate(&name, &label_font, max_width as f32 - 2.0 * LABEL_PADDING);
+            let (label_width, _) = label_font.measure_str(&label, None);
+
+            let mut label_paint = Paint::default();
+            label_paint.set_color(Color::WHITE);
+            label_paint.set_anti_alias(true);
+            canvas.draw_str(
+                &label,
+                (
+                    x_offset as f32 + (max_width as f32 - label_width) / 2.0,
+                    (y_offset + cover_height) as f32 + LABEL_PADDING - label_metrics.ascent,
+                ),
+                &label_font,
+                &label_paint,
+            );
+        }
 
-        if ofcs[usize::try_from(i).unwrap()].selected {
+        if !hide_chrome && ofcs[usize::try_from(i).unwrap()].selected {
             canvas.draw_rect(
                 Rect {
                     left: x_offset as f32,
@@ -123,27 +395,118 @@ pub fn render_frame(
                 &paint,
             );
         }
+
+        if !hide_chrome && focused_in_page == Some(usize::try_from(i).unwrap()) {
+            // Inset so that the whole border is within the cell
+            let inset = FOCUS_BORDER_WIDTH / 2.0;
+            canvas.draw_rect(
+                Rect {
+                    left: x_offset as f32 + inset,
+                    top: y_offset as f32 + inset,
+                    right: (x_offset + max_width) as f32 - inset,
+                    bottom: (y_offset + max_height) as f32 - inset,
+                },
+                &focus_paint,
+            );
+        }
+    }
+
+    if !hide_chrome {
+        render_page_indicator(state, screen_width, canvas);
     }
 }
 
-fn decode_images(ofcs: &[Ofc]) -> Vec<DecodedImage> {
+/// Draws which page is being shown, out of how many, in the top right corner.
+fn render_page_indicator(state: &Screen, screen_width: i32, canvas: &skia_safe::Canvas) {
+    const PADDING: f32 = 8.0;
+    const MARGIN: f32 = 16.0;
+
+    let message = format!("Page {} / {}", state.page_index + 1, state.page_count());
+
+    let font = text::font(20.0);
+    let (text_width, _) = font.measure_str(&message, None);
+    let (_, metrics) = font.metrics();
+
+    let box_width = text_width + 2.0 * PADDING;
+    let box_height = metrics.descent - metrics.ascent + 2.0 * PADDING;
+
+    let left = screen_width as f32 - MARGIN - box_width;
+    let top = MARGIN;
+
+    // A translucent background keeps the text legible over covers of any colour
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_round_rect(
+        Rect {
+            left,
+            top,
+            right: left + box_width,
+            bottom: top + box_height,
+        },
+        8.0,
+        8.0,
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    canvas.draw_str(
+        &message,
+        (left + PADDING, top + PADDING - metrics.ascent),
+        &font,
+        &paint,
+    );
+}
+
+/// Returns the size of each cell in a grid of `columns` x `rows` filling the screen, or `None`
+/// if the cells are too small to draw covers in.
+fn cell_size(screen_width: i32, screen_height: i32, columns: i32, rows: i32) -> Option<ISize> {
+    let width = screen_width / columns;
+    let height = screen_height / rows;
+
+    // Covers can't be made out in cells this small, so don't spend time decoding them
+    (width >= MIN_CELL_SIZE && height >= MIN_CELL_SIZE).then(|| ISize::new(width, height))
+}
+
+fn decode_images(
+    paths: &[PathBuf],
+    cover: Cover,
+    cache: &ImageCache,
+    cell_size: ISize,
+) -> Vec<Option<DecodedImage>> {
+    // A thread per core, so that large grids don't start more threads than can run at once
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(paths.len());
+    let next_index = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
     thread::scope(|s| {
-        let handles: Vec<_> = ofcs
-            .iter()
-            .map(|ofc| {
-                s.spawn(|| {
-                    let image_bytes = load_image_bytes(&ofc.path);
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let next_index = &next_index;
+            s.spawn(move || {
+                loop {
+                    let i = next_index.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else {
+                        break;
+                    };
+
+                    // The receiver outlives the scope, so this can't fail
+                    let _ = sender.send((i, load_cover(path, cover, cache, cell_size)));
+                }
+            });
+        }
+    });
+    drop(sender);
 
-                    decode_image(&image_bytes)
-                })
-            })
-            .collect();
+    // Covers finish in any order, so they're put back in the order of their paths
+    let mut images = vec![None; paths.len()];
+    for (i, image) in receiver {
+        images[i] = image;
+    }
 
-        handles
-            .into_iter()
-            .map(|h| h.join().unwrap())
-            .collect::<Vec<_>>()
-    })
+    images
 }
 
 fn draw_cover(
@@ -151,12 +514,50 @@ fn draw_cover(
     decoded_image: DecodedImage,
     mut x_offset: i32,
     mut y_offset: i32,
-    max_width: i32,
-    max_height: i32,
+    mut max_width: i32,
+    mut max_height: i32,
+    aspect_ratio: Option<f32>,
 ) {
     let DecodedImage { image, info } = decoded_image;
 
-    let (width, height) = scale_to_fit(&info, max_width, max_height);
+    if let Some(aspect_ratio) = aspect_ratio {
+        // Shrink the cell to the largest box with the aspect ratio that fits in it. The cover
+        // fills the box, and whatever overflows it is cropped.
+        let (box_width, box_height) = if max_width as f32 / max_height as f32 > aspect_ratio {
+            ((max_height as f32 * aspect_ratio) as i32, max_height)
+        } else {
+            (max_width, (max_width as f32 / aspect_ratio) as i32)
+        };
+
+        x_offset += (max_width - box_width) / 2;
+        y_offset += (max_height - box_height) / 2;
+        max_width = box_width;
+        max_height = box_height;
+    }
+
+    let (width, height) = match aspect_ratio {
+        Some(_) => scale_to_fill(&info, max_width, max_height),
+        None => scale_to_fit(&info, max_width, max_height),
+    };
+
+    // Nothing would be visible of a cover which is less than a pixel in either direction
+    if width.min(height).min(max_width).min(max_height) < 1 {
+        return;
+    }
+
+    if aspect_ratio.is_some() {
+        canvas.save();
+        canvas.clip_rect(
+            Rect {
+                left: x_offset as f32,
+                top: y_offset as f32,
+                right: (x_offset + max_width) as f32,
+                bottom: (y_offset + max_height) as f32,
+            },
+            ClipOp::Intersect,
+            true,
+        );
+    }
 
     x_offset += (max_width - width) / 2;
     y_offset += (max_height - height) / 2;
@@ -173,36 +574,53 @@ fn draw_cover(
         SAMPLING_OPTIONS,
         &Paint::default(),
     );
-}
 
-struct DecodedImage {
-    image: Image,
-    info: ImageInfo,
+    if aspect_ratio.is_some() {
+        canvas.restore();
+    }
 }
 
-fn decode_image(bytes: &[u8]) -> DecodedImage {
-    let mut c = Cursor::new(&bytes);
+/// Fills a cell with gray in place of a cover which couldn't be decoded.
+fn draw_placeholder(
+    canvas: &skia_safe::Canvas,
+    x_offset: i32,
+    y_offset: i32,
+    max_width: i32,
+    max_height: i32,
+) {
+    let mut paint = Paint::default();
+    paint.set_color(0xFF404040);
 
-    let mut codec = if bytes.starts_with(b"\xFF\xD8\xFF") {
-        jpeg_decoder::decode_stream(&mut c).unwrap()
-    } else if bytes.starts_with(b"\x89PNG\x0D\x0A\x1A\x0A") {
-        png_decoder::decode_stream(&mut c).unwrap()
-    } else if bytes.len() > b"RIFF\0\0\0\0WEBPVP".len()
-        && bytes.starts_with(b"RIFF")
-        && &bytes[8..][..6] == b"WEBPVP"
-    {
-        webp_decoder::decode_stream(&mut c).unwrap()
-    } else {
-        panic!("unsupported file type");
-    };
+    // Leave a gap between neighbouring placeholders so they can be told apart
+    let margin = (max_width.min(max_height) / 20) as f32;
 
-    DecodedImage {
-        image: codec.get_image(codec.info(), None).unwrap(),
-        info: codec.info(),
-    }
+    canvas.draw_rect(
+        Rect {
+            left: x_offset as f32 + margin,
+            top: y_offset as f32 + margin,
+            right: (x_offset + max_width) as f32 - margin,
+            bottom: (y_offset + max_height) as f32 - margin,
+        },
+        &paint,
+    );
 }
 
 fn scale_to_fit(info: &ImageInfo, max_width: i32, max_height: i32) -> (i32, i32) {
+    // Use the smaller scaling factor to fit within the window
+    scale(info, max_width, max_height, f32::min)
+}
+
+fn scale_to_fill(info: &ImageInfo, max_width: i32, max_height: i32) -> (i32, i32) {
+    // Use the larger scaling factor to cover the whole window
+    scale(info, max_width, max_height, f32::max)
+}
+
+fn scale(
+    info: &ImageInfo,
+    max_width: i32,
+    max_height: i32,
+    choose: fn(f32, f32) -> f32,
+) -> (i32, i32) {
     let ISize {
         mut width,
         mut height,
@@ -212,8 +630,7 @@ fn scale_to_fit(info: &ImageInfo, max_width: i32, max_height: i32) -> (i32, i32)
     let scale_x = max_width as f32 / width as f32;
     let scale_y = max_height as f32 / height as f32;
 
-    // Use the smaller scaling factor to fit within the window
-    let scale = scale_x.min(scale_y);
+    let scale = choose(scale_x, scale_y);
 
     width = (width as f32 * scale) as i32;
     height = (height as f32 * scale) as i32;
@@ -221,25 +638,123 @@ fn scale_to_fit(info: &ImageInfo, max_width: i32, max_height: i32) -> (i32, i32)
     (width, height)
 }
 
-fn load_image_bytes(p: &Path) -> Vec<u8> {
-    let mut f = File::open(p).unwrap();
+/// Loads the cover of the file at `p` at about the size of a cell of `cell_size`, or `None` if
+/// it has no cover which can be decoded.
+pub fn load_cover(
+    p: &Path,
+    cover: Cover,
+    cache: &ImageCache,
+    cell_size: ISize,
+) -> Option<DecodedImage> {
+    let mut file = FileContainer::open_lazy(p).ok()?;
+    if file.is_empty() {
+        return None;
+    }
+
+    let index = match cover {
+        Cover::First => 0,
+        Cover::Middle => file.len() / 2,
+        Cover::Largest => largest_image_index(&mut file),
+        Cover::Index(index) => index.min(file.len() - 1),
+    };
+
+    // Reuse the full size image if the viewer has already decoded it. Otherwise only decode as
+    // much of it as will be drawn, since covers are far smaller than the images often are.
+    if let Some(frames) = cache.get(p, index) {
+        return frames.into_iter().next().map(|frame| frame.image);
+    }
+
+    if matches!(cover, Cover::First)
+        && let Some(thumbnail) = embedded_thumbnail(&mut file, cell_size)
+    {
+        return Some(thumbnail);
+    }
+
+    let bytes = file.read_at(index).ok()?;
+    with_decode_order(file.decode_order().as_deref(), || {
+        decode_image_to_cover(&bytes, cell_size)
+    })
+    .ok()
+}
+
+/// Decodes the thumbnail stored in the container, if it has one and it's large enough to fill
+/// a cell of `cell_size` without being scaled up.
+fn embedded_thumbnail(file: &mut FileContainer, cell_size: ISize) -> Option<DecodedImage> {
+    let thumbnail = decode_image(&file.metadata().ok()?.thumbnail?).ok()?;
+    let ISize { width, height } = thumbnail.info.dimensions();
 
-    let mut buf = [0u8; 16];
-    f.read_exact(&mut buf).unwrap();
+    (width >= cell_size.width || height >= cell_size.height).then_some(thumbnail)
+}
 
-    assert_eq!(&buf[..4], b"ofc\0");
+/// Returns the index of the image with the most encoded bytes, which is usually the one with the
+/// largest area. Sizes come from the offset table, so no images are read to find it.
+fn largest_image_index(file: &mut FileContainer) -> usize {
+    let mut largest: Option<(u64, usize)> = None;
 
-    let num_files = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    for i in 0..file.len() {
+        // Images whose offsets can't be read are only used if there's nothing else
+        let len = file.image_len(i).unwrap_or(0);
 
-    let end_offset = u64::from_le_bytes([
-        buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15],
-    ]);
+        if largest
+            .as_ref()
+            .is_none_or(|(largest_len, _)| len > *largest_len)
+        {
+            largest = Some((len, i));
+        }
+    }
 
-    f.seek(std::io::SeekFrom::Start(8 + 8 * u64::from(num_files)))
-        .unwrap();
+    largest.map_or(0, |(_, i)| i)
+}
 
-    let mut buf = vec![0u8; usize::try_from(end_offset).unwrap()];
-    f.read_exact(&mut buf).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    buf
+    fn screen(len: usize) -> Screen {
+        let paths = (0..len)
+            .map(|i| PathBuf::from(format!("{i}.ofc")))
+            .collect();
+        Screen::new(paths, ImageCache::new(0))
+    }
+
+    #[test]
+    fn cell_size_divides_the_screen_between_cells() {
+        assert_eq!(cell_size(800, 600, 4, 3), Some(ISize::new(200, 200)));
+        // Leftover pixels are left as a gap at the right and bottom edges
+        assert_eq!(cell_size(803, 602, 4, 3), Some(ISize::new(200, 200)));
+    }
+
+    #[test]
+    fn cell_size_allows_cells_of_the_minimum_size() {
+        assert_eq!(
+            cell_size(4 * MIN_CELL_SIZE, 3 * MIN_CELL_SIZE, 4, 3),
+            Some(ISize::new(MIN_CELL_SIZE, MIN_CELL_SIZE))
+        );
+    }
+
+    #[test]
+    fn cell_size_rejects_cells_smaller_than_the_minimum() {
+        assert_eq!(cell_size(4 * MIN_CELL_SIZE - 1, 600, 4, 3), None);
+        assert_eq!(cell_size(800, 3 * MIN_CELL_SIZE - 1, 4, 3), None);
+        assert_eq!(cell_size(800, 600, 800, 1), None);
+    }
+
+    #[test]
+    fn selecting_and_deselecting_all_keeps_the_focus_and_page() {
+        let mut screen = screen(30);
+        screen.next_page();
+        screen.focus_right();
+        screen.focus_right();
+        let focused = screen.focused;
+
+        screen.select_all();
+        assert!(screen.ofcs.iter().all(|ofc| ofc.selected));
+        assert_eq!(screen.page_index, 1);
+        assert_eq!(screen.focused, focused);
+
+        screen.deselect_all();
+        assert!(screen.ofcs.iter().all(|ofc| !ofc.selected));
+        assert_eq!(screen.page_index, 1);
+        assert_eq!(screen.focused, focused);
+    }
 }
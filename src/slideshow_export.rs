@@ -0,0 +1,149 @@
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use gallery_desktop::{FileContainer, decode_image_to_cover, with_decode_order};
+use skia_safe::{Color, EncodedImageFormat, ISize, Surface, surfaces};
+
+use crate::viewer::{self, DrawnImage, Transition};
+
+/// The number of frames to render for each second of the slideshow, unless another rate is
+/// configured
+pub const DEFAULT_FPS: u32 = 30;
+
+/// The size of the frames to render, unless another size is configured
+pub const DEFAULT_SIZE: (u32, u32) = (1920, 1080);
+
+/// A slideshow to render to numbered PNG files at a fixed frame rate, for putting together
+/// into a video with a tool like ffmpeg, rather than showing it.
+pub struct SlideshowExport {
+    pub paths: Vec<PathBuf>,
+    /// The directory to write the frames to
+    pub out_dir: PathBuf,
+    pub fps: u32,
+    /// The size of each frame, in pixels
+    pub size: (u32, u32),
+    /// How long to show each image for
+    pub interval: Duration,
+    pub transition: Transition,
+}
+
+impl SlideshowExport {
+    /// Renders the first frame of every image in every file, in order, fitted to the frame size
+    /// on a black background, and changing from one to the next as the viewer does. Images which
+    /// can't be read or decoded are skipped.
+    pub fn run(&self) -> io::Result<()> {
+        let (width, height) = self.size;
+        let (width, height) = (
+            i32::try_from(width).map_err(io::Error::other)?,
+            i32::try_from(height).map_err(io::Error::other)?,
+        );
+        let mut surface = surfaces::raster_n32_premul(ISize::new(width, height))
+            .ok_or_else(|| io::Error::other("couldn't create a surface to render to"))?;
+
+        fs::create_dir_all(&self.out_dir)?;
+
+        let frames_per_image = frame_count(self.interval, self.fps).max(1);
+        let transition_frames = match self.transition {
+            Transition::None => 0,
+            Transition::Fade => frame_count(viewer::FADE_DURATION, self.fps),
+        }
+        .min(frames_per_image);
+
+        let mut previous: Option<DrawnImage> = None;
+        let mut frame_number = 0;
+        for path in &self.paths {
+            let mut file = match FileContainer::open_lazy(path) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            let decode_order = file.decode_order();
+            for index in 0..file.len() {
+                // Images larger than the frames are only decoded at about the size they're drawn
+                let image = match file.read_at(index) {
+                    Ok(bytes) => with_decode_order(decode_order.as_deref(), || {
+                        decode_image_to_cover(&bytes, ISize::new(width, height))
+                    })
+                    .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                let image = match image {
+                    Ok(image) => image,
+                    Err(e) => {
+                        eprintln!("skipping image {index} of {}: {e}", path.display());
+                        continue;
+                    }
+                };
+                let drawn = viewer::fit_to_screen(image.image, width, height);
+
+                // Frames in the middle of a transition each differ, but the rest are all the
+                // same, so that one is only rendered and encoded once
+                let mut still: Option<Vec<u8>> = None;
+                for frame in 0..frames_per_image {
+                    let previous = previous.as_ref().filter(|_| frame < transition_frames);
+                    let data = match (&still, previous) {
+                        (Some(still), None) => Cow::Borrowed(still.as_slice()),
+                        _ => {
+                            let canvas = surface.canvas();
+                            canvas.clear(Color::BLACK);
+
+                            // The image fades in over the previous one, as it does in the viewer
+                            let progress = match previous {
+                                Some(previous) => {
+                                    let progress = frame as f32 / transition_frames as f32;
+                                    previous.draw(1.0 - progress, canvas);
+                                    progress
+                                }
+                                None => 1.0,
+                            };
+                            drawn.draw(progress, canvas);
+
+                            Cow::Owned(encode(&mut surface)?)
+                        }
+                    };
+
+                    write_frame(&self.out_dir, frame_number, &data)?;
+                    frame_number += 1;
+
+                    if previous.is_none() && still.is_none() {
+                        still = Some(data.into_owned());
+                    }
+                }
+
+                previous = Some(drawn);
+            }
+        }
+        eprintln!();
+
+        println!("{}", self.out_dir.display());
+
+        Ok(())
+    }
+}
+
+/// Returns the number of frames which `duration` lasts for at `fps`.
+fn frame_count(duration: Duration, fps: u32) -> usize {
+    (duration.as_secs_f64() * f64::from(fps)).round() as usize
+}
+
+fn write_frame(out_dir: &Path, frame_number: usize, data: &[u8]) -> io::Result<()> {
+    fs::write(out_dir.join(format!("{frame_number:06}.png")), data)?;
+    eprint!("\rwrote {} frames", frame_number + 1);
+
+    Ok(())
+}
+
+fn encode(surface: &mut Surface) -> io::Result<Vec<u8>> {
+    surface
+        .image_snapshot()
+        .encode(None, EncodedImageFormat::PNG, None)
+        .map(|data| data.as_bytes().to_vec())
+        .ok_or_else(|| io::Error::other("couldn't encode a frame as PNG"))
+}
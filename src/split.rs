@@ -0,0 +1,61 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use gallery_desktop::{
+    ImageFormat, MappedContainer, decode_image, read_in_parallel, with_decode_order,
+};
+use skia_safe::EncodedImageFormat;
+
+use crate::annotation;
+
+/// Writes every image in the container at `path` to its own file in `out_dir`, named by its
+/// index in the container. With `label`, each image is decoded, has the file's name and its
+/// page number drawn onto it, and is written as a PNG, rather than being copied as it is.
+/// Images are written on a thread per core, and how fast they were read through is printed at
+/// the end.
+pub fn split(path: &Path, out_dir: &Path, label: bool) -> io::Result<()> {
+    // Every offset is needed, and reading them all up front means a corrupt table is reported
+    // before any images are written out. The file is mapped so that every thread can read
+    // from it at once.
+    let file = MappedContainer::open(path).map_err(io::Error::other)?;
+    let len = file.len();
+
+    fs::create_dir_all(out_dir)?;
+
+    // Pad the names so that they sort in the same order as in the container
+    let width = len.to_string().len();
+    let written = AtomicUsize::new(0);
+
+    let throughput = read_in_parallel(&file, |i, bytes| {
+        if label {
+            let image = with_decode_order(file.decode_order(), || decode_image(bytes))
+                .map_err(io::Error::other)?
+                .image;
+            let data = annotation::label_image(&image, &annotation::page_label(path, i))
+                .and_then(|image| image.encode(None, EncodedImageFormat::PNG, None))
+                .ok_or_else(|| io::Error::other(format!("couldn't label image {i}")))?;
+
+            fs::write(out_dir.join(format!("{i:0width$}.png")), data.as_bytes())?;
+        } else {
+            // The bytes are paged in from the map as they're written, so images too large to
+            // hold in memory can still be split out
+            let extension = ImageFormat::detect(bytes).map_or("bin", ImageFormat::extension);
+            fs::write(out_dir.join(format!("{i:0width$}.{extension}")), bytes)?;
+        }
+
+        eprint!(
+            "\rwrote {}/{len}",
+            written.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        Ok::<_, io::Error>(())
+    })?;
+    eprintln!();
+    eprintln!("read {throughput}");
+
+    println!("{}", out_dir.display());
+
+    Ok(())
+}
@@ -1,38 +1,365 @@
-use std::{ffi::OsString, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    env,
+    ffi::{OsStr, OsString},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+    time::Duration,
+};
 
-use crate::{selector, viewer};
+use gallery_desktop::{FileContainer, parse_decode_order, set_decode_order};
+
+use crate::{
+    decoder::Decoder,
+    image_cache::{self, ImageCache},
+    input::MouseBindings,
+    remote, scan, selector,
+    slideshow_export::{self, SlideshowExport},
+    viewer,
+};
 
 pub struct State {
     pub width: i32,
     pub height: i32,
     pub screen: Screen,
+    pub mouse_bindings: MouseBindings,
+    /// Decoded images, shared by every screen
+    cache: ImageCache,
+    decoder: Decoder,
+    /// Whether to hide every overlay and indicator on top of the images, leaving each screen's
+    /// own toggles as they were
+    pub hide_all_chrome: bool,
+    /// Whether to hide the mouse cursor over the viewer once the mouse has been still for a while
+    pub hide_idle_cursor: bool,
+    pub window_mode: WindowMode,
+    /// The width divided by the height to keep the window at in windowed mode, if it's locked to
+    /// one
+    pub window_aspect_ratio: Option<f32>,
+    /// Whether to wait for the display's vertical sync before showing each frame
+    pub vsync: bool,
+    /// Whether keys for checking how things are drawn, which aren't useful otherwise, are enabled
+    pub debug: bool,
+    /// Whether to show the keys which can be used on the current screen
+    pub show_help: bool,
+    viewer_config: viewer::Config,
+    /// A slideshow to render to files instead of opening a window, if one was asked for
+    pub slideshow_export: Option<SlideshowExport>,
+    /// Kept so that files downloaded from URLs which were passed in are removed on exit
+    _downloads: remote::Downloads,
+}
+
+/// The order to show files in
+#[derive(Clone, Copy, Default)]
+enum SortOrder {
+    /// The order they were passed in
+    #[default]
+    None,
+    /// By path, with runs of digits compared by their value, so that `2` comes before `10`
+    Natural,
+}
+
+impl SortOrder {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "none" => Self::None,
+            "natural" => Self::Natural,
+            _ => return None,
+        })
+    }
+}
+
+/// How the window is opened
+#[derive(Clone, Copy, Default)]
+pub enum WindowMode {
+    /// Borderless, covering the whole screen
+    #[default]
+    Fullscreen,
+    /// With decorations, optionally at a chosen size in logical pixels
+    Windowed { size: Option<(u32, u32)> },
 }
 
 pub enum Screen {
     Selector(selector::Screen),
-    Viewer(viewer::Screen),
+    Viewer(Box<viewer::Screen>),
+}
+
+/// The type of screen to show on launch
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InitialScreen {
+    Selector,
+    Viewer,
+}
+
+impl InitialScreen {
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Selector => "--select",
+            Self::Viewer => "--view",
+        }
+    }
 }
 
 impl State {
-    pub fn new(args: Vec<OsString>) -> Self {
-        let screen = match args.first().map(|arg| arg.as_encoded_bytes()) {
-            Some(b"--select") | Some(b"-s") => {
-                let mut args = args.into_iter();
-                args.next();
-
-                let paths = args.map(PathBuf::from).collect();
-                Screen::Selector(selector::Screen::new(paths))
+    pub fn new(args: Vec<OsString>, decoder: Decoder) -> Result<Self, String> {
+        let mut initial_screen = None;
+        let mut hide_idle_cursor = false;
+        let mut window_mode = WindowMode::default();
+        let mut window_aspect_ratio = None;
+        // On by default, so that frames aren't torn while images fade or animate
+        let mut vsync = true;
+        let mut recursive = false;
+        let mut debug = false;
+        let mut sort_order = SortOrder::default();
+        let mut cover_aspect_ratio = None;
+        let mut cover = selector::Cover::default();
+        let mut columns = selector::DEFAULT_COLUMNS;
+        let mut rows = selector::DEFAULT_ROWS;
+        let mut viewer_config = viewer::Config::default();
+        let mut mouse_bindings = MouseBindings::default();
+        let mut cache_bytes = image_cache::DEFAULT_BUDGET;
+        let mut paths = Vec::new();
+        let mut downloads = remote::Downloads::default();
+        let mut export_dir = None;
+        let mut export_fps = slideshow_export::DEFAULT_FPS;
+        let mut export_size = slideshow_export::DEFAULT_SIZE;
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_encoded_bytes() {
+                b"--select" | b"-s" => choose_screen(&mut initial_screen, InitialScreen::Selector)?,
+                b"--view" => choose_screen(&mut initial_screen, InitialScreen::Viewer)?,
+                b"--print-on-change" => viewer_config.print_on_change = true,
+                b"--hide-cursor" => hide_idle_cursor = true,
+                b"--recursive" => recursive = true,
+                b"--debug" => debug = true,
+                b"--sort" => {
+                    let value = flag_value(&mut args, "--sort")?;
+                    sort_order = SortOrder::parse(&value)
+                        .ok_or_else(|| format!("invalid sort order: {value}"))?;
+                }
+                b"--windowed" => {
+                    // The size is optional, so the next argument is only taken if it's a size
+                    let size = args
+                        .peek()
+                        .and_then(|arg| arg.to_str())
+                        .and_then(parse_window_size);
+                    if size.is_some() {
+                        args.next();
+                    }
+                    window_mode = WindowMode::Windowed { size };
+                }
+                b"--aspect" => {
+                    let value = flag_value(&mut args, "--aspect")?;
+                    window_aspect_ratio = Some(parse_aspect_ratio(&value)?);
+                }
+                b"--vsync" => {
+                    let value = flag_value(&mut args, "--vsync")?;
+                    vsync = match value.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(format!("invalid vsync setting: {value}")),
+                    };
+                }
+                b"--preview-file-switches" => viewer_config.preview_file_switches = true,
+                b"--wrap" => viewer_config.wrap = true,
+                b"--allow-delete" => viewer_config.allow_delete = true,
+                b"--label-exports" => viewer_config.label_exports = true,
+                b"--cover" => {
+                    let value = flag_value(&mut args, "--cover")?;
+                    cover = selector::Cover::parse(&value)
+                        .ok_or_else(|| format!("invalid cover: {value}"))?;
+                    viewer_config.cover = cover;
+                }
+                b"--cols" => columns = parse_grid_size(&flag_value(&mut args, "--cols")?)?,
+                b"--rows" => rows = parse_grid_size(&flag_value(&mut args, "--rows")?)?,
+                b"--cover-aspect" => {
+                    let value = flag_value(&mut args, "--cover-aspect")?;
+                    cover_aspect_ratio = Some(parse_aspect_ratio(&value)?);
+                }
+                b"--slideshow-secs" => {
+                    let value = flag_value(&mut args, "--slideshow-secs")?;
+                    viewer_config.slideshow_interval = value
+                        .parse()
+                        .ok()
+                        .filter(|secs: &f64| *secs > 0.0)
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| format!("invalid slideshow interval: {value}"))?;
+                }
+                b"--slideshow-bar" => {
+                    let value = flag_value(&mut args, "--slideshow-bar")?;
+                    viewer_config.slideshow_bar = viewer::BarPosition::parse(&value)
+                        .ok_or_else(|| format!("invalid slideshow bar position: {value}"))?;
+                }
+                b"--slideshow-timer" => {
+                    let value = flag_value(&mut args, "--slideshow-timer")?;
+                    viewer_config.slideshow_timer = viewer::SlideshowTimer::parse(&value)
+                        .ok_or_else(|| format!("invalid slideshow timer: {value}"))?;
+                }
+                b"--transition" => {
+                    let value = flag_value(&mut args, "--transition")?;
+                    viewer_config.transition = viewer::Transition::parse(&value)
+                        .ok_or_else(|| format!("invalid transition: {value}"))?;
+                }
+                b"--marks-file" => {
+                    viewer_config.marks_path =
+                        PathBuf::from(flag_value(&mut args, "--marks-file")?);
+                }
+                b"--cache-bytes" => {
+                    let value = flag_value(&mut args, "--cache-bytes")?;
+                    cache_bytes = value
+                        .parse()
+                        .map_err(|_| format!("invalid cache size: {value}"))?;
+                }
+                b"--tile-above" => {
+                    let value = flag_value(&mut args, "--tile-above")?;
+                    let megapixels: f64 = value
+                        .parse()
+                        .ok()
+                        .filter(|megapixels: &f64| *megapixels > 0.0)
+                        .ok_or_else(|| format!("invalid number of megapixels: {value}"))?;
+                    viewer_config.tile_above_pixels = Some((megapixels * 1_000_000.0) as u64);
+                }
+                b"--prefetch" => {
+                    let value = flag_value(&mut args, "--prefetch")?;
+                    viewer_config.prefetch = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of images to prefetch: {value}"))?;
+                }
+                b"--preload-file-under" => {
+                    let value = flag_value(&mut args, "--preload-file-under")?;
+                    viewer_config.preload_file_under = Some(value.parse().map_err(|_| {
+                        format!("invalid number of images to preload files under: {value}")
+                    })?);
+                }
+                b"--decode-order" => {
+                    let value = flag_value(&mut args, "--decode-order")?;
+                    let order = parse_decode_order(&value)
+                        .ok_or_else(|| format!("invalid decode order: {value}"))?;
+                    set_decode_order(order)
+                        .map_err(|_| String::from("the decode order can only be given once"))?;
+                }
+                b"--export-slideshow" => {
+                    export_dir = Some(PathBuf::from(flag_value(&mut args, "--export-slideshow")?));
+                }
+                b"--export-fps" => {
+                    let value = flag_value(&mut args, "--export-fps")?;
+                    export_fps = value
+                        .parse()
+                        .ok()
+                        .filter(|fps| *fps > 0)
+                        .ok_or_else(|| format!("invalid frame rate: {value}"))?;
+                }
+                b"--export-size" => {
+                    let value = flag_value(&mut args, "--export-size")?;
+                    export_size = parse_window_size(&value)
+                        .ok_or_else(|| format!("invalid frame size: {value}"))?;
+                }
+                b"--bind-mouse" => {
+                    let spec = flag_value(&mut args, "--bind-mouse")?;
+                    mouse_bindings.bind(&spec)?;
+                }
+                _ if remote::is_url(&arg) => {
+                    paths.push(remote::fetch(&arg.to_string_lossy(), &mut downloads)?);
+                }
+                _ if is_glob(&arg) => paths.extend(expand_glob(&arg.to_string_lossy())?),
+                _ => paths.push(PathBuf::from(arg)),
             }
-            _ => {
-                let paths = args.into_iter().map(PathBuf::from).collect();
-                Screen::Viewer(viewer::Screen::new(paths))
+        }
+
+        // Directories are expanded once every flag is known, since --recursive can come after
+        // them
+        let mut paths = {
+            let mut expanded = Vec::new();
+            for path in paths {
+                if path.is_dir() {
+                    let files = scan::ofc_files(&path, recursive)
+                        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                    expanded.extend(files);
+                } else {
+                    expanded.push(path);
+                }
+            }
+            expanded
+        };
+
+        if let SortOrder::Natural = sort_order {
+            paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        }
+
+        // Leave out files which can't be opened, rather than failing when they're displayed
+        retain_openable(&mut paths);
+        if paths.is_empty() {
+            return Err("no files to display".to_string());
+        }
+
+        let slideshow_export = export_dir.map(|out_dir| SlideshowExport {
+            paths: paths.clone(),
+            out_dir,
+            fps: export_fps,
+            size: export_size,
+            interval: viewer_config.slideshow_interval,
+            transition: viewer_config.transition,
+        });
+
+        let cache = ImageCache::new(cache_bytes);
+
+        let screen = match initial_screen.unwrap_or(InitialScreen::Viewer) {
+            InitialScreen::Selector => {
+                let mut screen = selector::Screen::new(paths, cache.clone());
+                screen.cover_aspect_ratio = cover_aspect_ratio;
+                screen.cover = cover;
+                screen.columns = columns;
+                screen.rows = rows;
+                Screen::Selector(screen)
             }
+            InitialScreen::Viewer => Screen::Viewer(Box::new(viewer::Screen::new(
+                paths,
+                viewer_config.clone(),
+                cache.clone(),
+                decoder.clone(),
+            ))),
         };
 
-        Self {
+        Ok(Self {
             width: 0,
             height: 0,
             screen,
+            mouse_bindings,
+            cache,
+            decoder,
+            hide_all_chrome: false,
+            show_help: false,
+            window_mode,
+            window_aspect_ratio,
+            vsync,
+            debug,
+            hide_idle_cursor,
+            viewer_config,
+            slideshow_export,
+            _downloads: downloads,
+        })
+    }
+
+    pub fn toggle_chrome(&mut self) {
+        self.hide_all_chrome = !self.hide_all_chrome;
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+    }
+
+    /// Adds files to the ones being shown, such as ones dropped onto the window. The viewer
+    /// moves to the first of them.
+    pub fn add_files(&mut self, mut paths: Vec<PathBuf>) {
+        retain_openable(&mut paths);
+        if paths.is_empty() {
+            return;
+        }
+
+        match &mut self.screen {
+            Screen::Selector(screen) => screen.add_files(paths),
+            Screen::Viewer(screen) => screen.add_files(paths),
         }
     }
 
@@ -41,13 +368,172 @@ impl State {
             return;
         };
 
-        self.screen = Screen::Viewer(viewer::Screen::new(
+        self.screen = Screen::Viewer(Box::new(viewer::Screen::new(
             screen
                 .ofcs
                 .iter()
                 .filter(|ofc| ofc.selected)
                 .map(|ofc| ofc.path.clone())
                 .collect(),
-        ));
+            self.viewer_config.clone(),
+            self.cache.clone(),
+            self.decoder.clone(),
+        )));
     }
 }
+
+/// Leaves out files which can't be opened, rather than failing when they're displayed.
+fn retain_openable(paths: &mut Vec<PathBuf>) {
+    // Only headers are read, so that starting with many large files stays fast
+    paths.retain(|path| match FileContainer::open_lazy(path) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("skipping {}: {e}", path.display());
+            false
+        }
+    });
+}
+
+fn choose_screen(
+    initial_screen: &mut Option<InitialScreen>,
+    screen: InitialScreen,
+) -> Result<(), String> {
+    match initial_screen {
+        Some(chosen) if *chosen != screen => Err(format!(
+            "{} can't be used with {}",
+            screen.flag(),
+            chosen.flag()
+        )),
+        _ => {
+            *initial_screen = Some(screen);
+            Ok(())
+        }
+    }
+}
+
+fn flag_value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{flag} requires a value"))?
+        .into_string()
+        .map_err(|value| format!("invalid value for {flag}: {}", value.to_string_lossy()))
+}
+
+/// Compares strings like [`str::cmp`], except that runs of digits are compared by their value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+
+                // Without leading zeros, longer numbers are larger
+                let ordering = x
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y.trim_start_matches('0').len())
+                    .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')))
+                    // Leave equal numbers with different padding in a consistent order
+                    .then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+
+    digits
+}
+
+/// Returns whether a command line argument is a pattern matching paths rather than a path.
+/// Paths which exist are taken as they are, since names like `Vol [01].ofc` contain pattern
+/// characters.
+fn is_glob(arg: &OsStr) -> bool {
+    arg.as_encoded_bytes()
+        .iter()
+        .any(|b| matches!(b, b'*' | b'?' | b'['))
+        && !Path::new(arg).exists()
+}
+
+/// Returns the paths matching `pattern`, sorted by path. A leading `~` is expanded to the home
+/// directory, for patterns which were quoted to keep the shell from expanding them.
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = match (pattern.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => pattern.to_string(),
+    };
+
+    let paths: Vec<PathBuf> = glob::glob(&pattern)
+        .map_err(|e| format!("invalid pattern {pattern}: {e}"))?
+        .filter_map(|entry| match entry {
+            Ok(path) => Some(path),
+            Err(e) => {
+                eprintln!("skipping {}: {e}", e.path().display());
+                None
+            }
+        })
+        .collect();
+    if paths.is_empty() {
+        eprintln!("no files match {pattern}");
+    }
+
+    Ok(paths)
+}
+
+/// Parses a window size in the form `WxH`.
+fn parse_window_size(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    let width = width.parse().ok().filter(|width| *width > 0)?;
+    let height = height.parse().ok().filter(|height| *height > 0)?;
+
+    Some((width, height))
+}
+
+/// Parses the number of columns or rows in the selector's grid, which must be from 1 to
+/// [`selector::MAX_GRID_SIZE`].
+fn parse_grid_size(s: &str) -> Result<i32, String> {
+    s.parse()
+        .ok()
+        .filter(|size| (1..=selector::MAX_GRID_SIZE).contains(size))
+        .ok_or_else(|| {
+            format!(
+                "invalid grid size: {s} (must be from 1 to {})",
+                selector::MAX_GRID_SIZE
+            )
+        })
+}
+
+/// Parses an aspect ratio in the form `W:H`, returning `W / H`.
+fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+    let invalid = || format!("invalid aspect ratio: {s}");
+
+    let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+    let width: f32 = width.parse().map_err(|_| invalid())?;
+    let height: f32 = height.parse().map_err(|_| invalid())?;
+
+    if !(width > 0.0 && height > 0.0) {
+        return Err(invalid());
+    }
+
+    Ok(width / height)
+}
@@ -0,0 +1,67 @@
+use skia_safe::{Font, FontMgr, FontStyle, Typeface};
+
+thread_local! {
+    static TYPEFACE: Option<Typeface> =
+        FontMgr::new().legacy_make_typeface(None, FontStyle::normal());
+}
+
+/// Returns a font using the system's default typeface at the given size.
+pub fn font(size: f32) -> Font {
+    TYPEFACE.with(|typeface| match typeface {
+        Some(typeface) => Font::from_typeface(typeface.clone(), size),
+        None => {
+            let mut font = Font::default();
+            font.set_size(size);
+            font
+        }
+    })
+}
+
+/// Splits `text` into lines which fit within `max_width` when drawn with `font`, breaking
+/// between words. Words which are wider than `max_width` on their own are left unbroken.
+pub fn wrap(text: &str, font: &Font, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if line.is_empty() {
+            line.push_str(word);
+            continue;
+        }
+
+        let candidate = format!("{line} {word}");
+        let (width, _) = font.measure_str(&candidate, None);
+        if width <= max_width {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Shortens `text` to fit within `max_width` when drawn with `font`, ending it with an ellipsis
+/// if anything had to be cut off.
+pub fn truncate(text: &str, font: &Font, max_width: f32) -> String {
+    const ELLIPSIS: char = '\u{2026}';
+
+    let fits = |s: &str| font.measure_str(s, None).0 <= max_width;
+    if fits(text) {
+        return text.to_string();
+    }
+
+    let mut truncated = text.to_string();
+    while truncated.pop().is_some() {
+        let candidate = format!("{truncated}{ELLIPSIS}");
+        if fits(&candidate) {
+            return candidate;
+        }
+    }
+
+    ELLIPSIS.to_string()
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use skia_safe::{Color, Paint, Rect};
+
+use crate::text;
+
+const DURATION: Duration = Duration::from_millis(1500);
+
+/// A short message that is shown on top of the screen for a brief period of time.
+pub struct Toast {
+    message: String,
+    shown_at: Instant,
+}
+
+impl Toast {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            shown_at: Instant::now(),
+        }
+    }
+
+    pub fn expires_at(&self) -> Instant {
+        self.shown_at + DURATION
+    }
+
+    pub fn is_visible(&self) -> bool {
+        Instant::now() < self.expires_at()
+    }
+}
+
+pub fn render(toast: &Toast, screen_width: i32, screen_height: i32, canvas: &skia_safe::Canvas) {
+    const PADDING: f32 = 16.0;
+    const BOTTOM_MARGIN: f32 = 64.0;
+
+    let font = text::font(28.0);
+    let (text_width, _) = font.measure_str(&toast.message, None);
+    let (_, metrics) = font.metrics();
+
+    let box_width = text_width + 2.0 * PADDING;
+    let box_height = metrics.descent - metrics.ascent + 2.0 * PADDING;
+
+    let left = (screen_width as f32 - box_width) / 2.0;
+    let top = screen_height as f32 - BOTTOM_MARGIN - box_height;
+
+    let mut background = Paint::default();
+    background.set_color(0xCC000000);
+    canvas.draw_round_rect(
+        Rect {
+            left,
+            top,
+            right: left + box_width,
+            bottom: top + box_height,
+        },
+        8.0,
+        8.0,
+        &background,
+    );
+
+    let mut foreground = Paint::default();
+    foreground.set_color(Color::WHITE);
+    foreground.set_anti_alias(true);
+    canvas.draw_str(
+        &toast.message,
+        (left + PADDING, top + PADDING - metrics.ascent),
+        &font,
+        &foreground,
+    );
+}
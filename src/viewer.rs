@@ -1,16 +1,314 @@
+use arboard::{Clipboard, ImageData};
+use gallery_desktop::{Chapter, DecodedImage, Frame, MappedContainer, OfcError};
 use skia_safe::{
-    codec::{jpeg_decoder, png_decoder, webp_decoder},
-    Color, ISize, Paint, Rect, SamplingOptions,
+    AlphaType, Color, ColorType, EncodedImageFormat, IRect, ISize, Image, ImageFilter, ImageInfo,
+    Paint, PaintStyle, Rect, SamplingOptions, TileMode,
+    canvas::SrcRectConstraint,
+    gpu::{DirectContext, Mipmapped},
+    image::CachingHint,
+    image_filters,
 };
-use std::io::Cursor;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
 
-use crate::file_container::FileContainer;
+use crate::annotation;
+use crate::decoder::{Decoded, Decoder, LoadError, Loaded, Request};
+use crate::help::KeyBinding;
+use crate::image_cache::ImageCache;
+use crate::preloader::Preloader;
+use crate::selector::Cover;
+use crate::text;
+use crate::toast::{self, Toast};
+
+/// How long after switching files to keep showing a reduced resolution preview of the image
+const FILE_PREVIEW_DURATION: Duration = Duration::from_millis(300);
+const FILE_PREVIEW_SCALE: f32 = 0.25;
+
+/// The longest time to show the cover of the next file for when peeking at it
+const PEEK_DURATION: Duration = Duration::from_secs(3);
+
+/// The range of how far the current image can be zoomed in, relative to fitting it to the window
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 32.0;
+
+/// The least of a zoomed image, in pixels, to keep on screen in each direction while panning
+const MIN_VISIBLE_WHILE_PANNING: f32 = 64.0;
+
+/// The fraction of its speed that a flung image keeps after moving for a second
+const FLING_FRICTION: f32 = 0.02;
+/// The speed, in pixels per second, below which a flung image stops
+const FLING_MIN_SPEED: f32 = 20.0;
+/// How recently the image must have been dragged when it's let go of for it to keep moving.
+/// Images which were held still before being let go of stay where they are.
+const FLING_WINDOW: Duration = Duration::from_millis(50);
+
+/// The most pixels of an image too large to decode whole to decode at full size at once
+const MAX_REGION_PIXELS: u64 = 64 * 1024 * 1024;
+/// How much of the part of such an image on screen to decode around it in each direction, as a
+/// fraction of its size, so that it can be panned a little without decoding more
+const REGION_PADDING: f32 = 0.5;
+
+/// How many images to decode ahead of navigation, the way it last moved, unless another number
+/// is configured
+const DEFAULT_PREFETCH: usize = 3;
+
+/// How long to show frames of animated images for when they don't give a usable duration
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+/// How long to show each image for in a slideshow, unless another interval is configured
+const DEFAULT_SLIDESHOW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The range which the slideshow's interval can be sped up or slowed down within
+const MIN_SLIDESHOW_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_SLIDESHOW_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How long it takes for one image to fade into the next, when fading between them
+pub const FADE_DURATION: Duration = Duration::from_millis(200);
+
+/// The keys which can be used on the viewer, for the help overlay
+pub const KEY_BINDINGS: &[KeyBinding] = &[
+    ("j / k", "Next or previous image"),
+    ("h / l", "Next or previous file"),
+    ("Home / End", "First or last image in the file"),
+    ("Shift+Home / End", "First or last image overall"),
+    ("0-9, Enter", "Go to an image by its number"),
+    ("} / {", "Next or previous chapter"),
+    ("n (hold)", "Peek at the next file"),
+    ("s", "Start or stop the slideshow"),
+    ("[ / ]", "Speed up or slow down the slideshow"),
+    ("L", "Loop within the file"),
+    ("f", "Change how images fit the window"),
+    ("0", "Reset the zoom"),
+    ("K", "Keep flung images moving"),
+    ("r / R", "Rotate clockwise or counterclockwise"),
+    ("b", "Sharpen monochrome images"),
+    ("p", "Show progress as dots, a number, or not at all"),
+    ("t", "Show captions"),
+    ("i", "Show file info"),
+    ("d", "Show when images are decoding in the background"),
+    ("I", "Show the zoom, rotation, and filters"),
+    ("o", "Print the position"),
+    ("w", "Export the image as a PNG file"),
+    ("c", "Copy the image"),
+    ("x", "Remove the file"),
+    ("m", "Mark the image"),
+    ("F5", "Retry loading the image"),
+    ("F11", "Toggle fullscreen"),
+    ("Print Screen", "Save a screenshot"),
+    ("`", "Hide overlays"),
+    ("?", "Show or hide this help"),
+    ("Super+Q", "Quit"),
+];
+
+/// Where to write the list of marked images, unless another path is configured
+const DEFAULT_MARKS_PATH: &str = "marks.txt";
+
+/// How long to show that there are no more images for, after trying to move past the first or
+/// last one
+const BOUNDARY_CUE_DURATION: Duration = Duration::from_millis(300);
+
+/// The width, in pixels, of the frame drawn around marked images
+const MARK_FRAME_WIDTH: f32 = 8.0;
+
+/// Settings for the viewer which are fixed for the whole session
+#[derive(Clone)]
+pub struct Config {
+    /// Whether to show a reduced resolution preview of the image after switching files, until
+    /// navigation settles, so that skimming through files is fast
+    pub preview_file_switches: bool,
+    /// Whether navigating past the last image of the last file wraps around to the first image
+    /// of the first file, and the reverse
+    pub wrap: bool,
+    /// How long to show each image for before moving on to the next one in a slideshow, until
+    /// it's sped up or slowed down
+    pub slideshow_interval: Duration,
+    /// Whether removing a file also moves it to the trash
+    pub allow_delete: bool,
+    /// Where to write the list of marked images on exit
+    pub marks_path: PathBuf,
+    /// How to change from one image to the next
+    pub transition: Transition,
+    /// Which image to show from the next file when peeking at it
+    pub cover: Cover,
+    /// Images with more pixels than this are shown from an overview at reduced resolution, with
+    /// the part on screen decoded at full size as it's zoomed into, rather than decoded whole
+    pub tile_above_pixels: Option<u64>,
+    /// How many images to decode ahead of navigation, the way it last moved
+    pub prefetch: usize,
+    /// Files with fewer images than this are decoded whole in the background, as far as the
+    /// cache can hold them, so that moving anywhere within them is instant
+    pub preload_file_under: Option<usize>,
+    /// Where to show how long is left until the slideshow moves on, if anywhere
+    pub slideshow_bar: Option<BarPosition>,
+    /// When the slideshow starts timing how long each image has been shown for
+    pub slideshow_timer: SlideshowTimer,
+    /// Whether to draw the name of the file and the page number onto exported images
+    pub label_exports: bool,
+    /// Whether to print the position whenever it changes, however it was moved
+    pub print_on_change: bool,
+}
+
+/// Which edge of the window a bar is drawn along.
+#[derive(Clone, Copy)]
+pub enum BarPosition {
+    Top,
+    Bottom,
+}
+
+impl BarPosition {
+    /// Parses a position, or `none` for no bar.
+    pub fn parse(s: &str) -> Option<Option<Self>> {
+        Some(match s {
+            "top" => Some(Self::Top),
+            "bottom" => Some(Self::Bottom),
+            "none" => None,
+            _ => return None,
+        })
+    }
+}
+
+/// When the slideshow starts timing how long an image has been shown for
+#[derive(Clone, Copy, Default)]
+pub enum SlideshowTimer {
+    /// As soon as it's moved to, even if it's still being decoded
+    Switch,
+    /// Once it's been decoded and drawn at full resolution, so that images which are slow to
+    /// decode are still seen for the whole interval
+    #[default]
+    Display,
+}
+
+impl SlideshowTimer {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "switch" => Self::Switch,
+            "display" => Self::Display,
+            _ => return None,
+        })
+    }
+}
+
+/// How to change from one image to the next when navigating
+#[derive(Clone, Copy, Default)]
+pub enum Transition {
+    /// Replace the image straight away
+    #[default]
+    None,
+    /// Fade the previous image out while fading the next one in
+    Fade,
+}
+
+impl Transition {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "none" => Self::None,
+            "fade" => Self::Fade,
+            _ => return None,
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            preview_file_switches: false,
+            wrap: false,
+            slideshow_interval: DEFAULT_SLIDESHOW_INTERVAL,
+            allow_delete: false,
+            marks_path: PathBuf::from(DEFAULT_MARKS_PATH),
+            transition: Transition::default(),
+            cover: Cover::default(),
+            tile_above_pixels: None,
+            prefetch: DEFAULT_PREFETCH,
+            preload_file_under: None,
+            slideshow_bar: Some(BarPosition::Bottom),
+            slideshow_timer: SlideshowTimer::default(),
+            label_exports: false,
+            print_on_change: false,
+        }
+    }
+}
 
 pub struct Screen {
-    show_progress: bool,
+    config: Config,
+    progress_display: ProgressDisplay,
+    show_captions: bool,
+    /// Whether to show the path of the current file and the position and size of the current
+    /// image
+    show_info: bool,
+    /// Whether to show how the current image is zoomed, panned, rotated, and filtered
+    show_view_state: bool,
+    /// Whether to show a dot while images are being decoded in the background, to tell slow
+    /// decodes apart from other stutters
+    show_decoding: bool,
+    /// Whether navigating past either end of the current file wraps around within it
+    loop_within_file: bool,
+    /// Whether to sharpen grayscale images, such as scanned pages of text, to make them easier
+    /// to read
+    sharpen_monochrome: bool,
+    toast: Option<Toast>,
+    /// The error from the last attempt to load the current image, if it failed
+    last_error: Option<LoadError>,
+    /// When the current file was last switched to with `next_file` or `previous_file`
+    file_switched_at: Option<Instant>,
+    /// When peeking at the cover of the next file started, if it's being peeked at
+    peek_started_at: Option<Instant>,
+    /// The cover of the file which was last peeked at
+    peeked_cover: Option<PeekedCover>,
+    /// The path of the file whose cover was last requested from `decoder`, until it's decoded
+    pending_cover: Option<PathBuf>,
+    /// The current image once it's been decoded, kept until navigating away from it
+    current_image: Option<CurrentImage>,
+    /// How the current image was last drawn
+    last_drawn: Option<DrawnImage>,
+    /// The previous image and when it started fading out, while fading to the current one
+    fading_out: Option<(DrawnImage, Instant)>,
+    fit_mode: FitMode,
+    zoom: Zoom,
+    /// When the current image was shown in the slideshow, or `None` if a slideshow isn't running
+    slideshow_image_shown_at: Option<Instant>,
+    /// Whether the slideshow is waiting for the current image to be drawn before timing how
+    /// long it's been shown for
+    slideshow_waiting: bool,
+    /// How long to show each image for in the slideshow
+    slideshow_interval: Duration,
+    /// The images which have been marked, by the path of their file and their index in it.
+    /// They're kept by path so that they aren't affected by files being added or removed.
+    marked: HashSet<(PathBuf, usize)>,
+    /// The end of the images which was last tried to be moved past, and when that happened
+    boundary_hit: Option<(Boundary, Instant)>,
+    /// The system clipboard, once something has been copied to it
+    clipboard: Option<Clipboard>,
+    /// The digits of the image number being typed to jump to, if one is being typed
+    typed_number: Option<String>,
+    /// How many clockwise quarter turns the current image is rotated by, from 0 to 3
+    quarter_turns: u8,
+    /// Whether images keep moving and slow down after being let go of while dragged, rather
+    /// than stopping straight away
+    kinetic_panning: bool,
+    /// How fast the current image was last being dragged, while it's being dragged
+    drag: Option<Motion>,
+    /// How fast the current image is moving after being let go of, until it slows to a stop
+    coasting: Option<Motion>,
+    cache: ImageCache,
+    preloader: Preloader,
+    decoder: Decoder,
+    /// The image which was last requested from `decoder`, until it's decoded or navigation
+    /// moves away from it
+    pending_decode: Option<Request>,
+    /// Which way `next_image` or `previous_image` last moved, which preloading favours
+    direction: Option<Direction>,
+    /// The position when it was last checked for changes, for printing it when it changes
+    checked_position: Option<(usize, usize)>,
     paths: Paths,
-    current_file: CurrentFile,
+    /// The index of the image that each file was last left at, keyed by the file's path so that
+    /// they aren't affected by files being added or removed
+    reading_positions: HashMap<PathBuf, usize>,
+    /// The file being displayed, or `None` if there are no images to display
+    current_file: Option<CurrentFile>,
 }
 
 pub struct Paths {
@@ -20,167 +318,2804 @@ pub struct Paths {
 }
 
 pub struct CurrentFile {
-    file: FileContainer,
+    /// The file's images, or why it couldn't be opened. It's shared with the threads which
+    /// decode its images, so that they're decoded straight from the mapping.
+    file: Result<Arc<MappedContainer>, OfcError>,
+    /// Where each chapter of the file starts, in order
+    chapters: Vec<Chapter>,
+    /// The caption for each image in the file, from the file's sidecar captions file
+    captions: Vec<String>,
     /// The index of the image within the current file to display
     index: usize,
 }
 
-impl Screen {
-    pub fn new(paths: Vec<PathBuf>) -> Self {
+impl CurrentFile {
+    fn open(path: &Path) -> Self {
+        // Files are checked when they're passed in, so they only fail to open if they've
+        // changed since, or the drive they're on was disconnected
+        let file = MappedContainer::open(path).map(Arc::new);
+
+        // Files with unreadable chapters can still be read through, so they're left out
+        let chapters = match &file {
+            Ok(file) => file.chapters().unwrap_or_else(|e| {
+                eprintln!("failed to read the chapters of {}: {e}", path.display());
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
         Self {
-            current_file: CurrentFile {
-                file: FileContainer::open(&paths[0]),
-                index: 0,
-            },
-            paths: Paths {
-                data: paths,
-                index: 0,
-            },
-            show_progress: false,
+            file,
+            chapters,
+            captions: load_captions(path),
+            index: 0,
         }
     }
 
-    pub fn current_image_bytes(&mut self) -> Vec<u8> {
-        self.current_file.file.read_at(self.current_file.index)
+    /// Returns the number of images in the file, which is 0 if it couldn't be opened.
+    fn len(&self) -> usize {
+        self.file.as_ref().map_or(0, |file| file.len())
     }
 
-    pub fn next_image(&mut self) {
-        if self.current_file.index == self.current_file.file.len() - 1 {
-            if self.paths.index == self.paths.data.len() - 1 {
-                return;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the index of the last image in the file, which is 0 for files with no images.
+    fn last_index(&self) -> usize {
+        self.len().saturating_sub(1)
+    }
+
+    /// Returns the chapter which the current image is in, if the file has chapters.
+    fn chapter(&self) -> Option<&Chapter> {
+        self.chapters
+            .iter()
+            .rev()
+            .find(|chapter| chapter.index <= self.index)
+    }
+
+    fn caption(&self) -> Option<&str> {
+        self.captions
+            .get(self.index)
+            .map(String::as_str)
+            .filter(|caption| !caption.is_empty())
+    }
+}
+
+/// Loads the captions for the images in the container at `path`. They're stored next to the
+/// container in a file with a `captions` extension, one line per image.
+fn load_captions(path: &Path) -> Vec<String> {
+    let captions_path = path.with_extension("captions");
+
+    match fs::read_to_string(&captions_path) {
+        Ok(captions) => captions.lines().map(String::from).collect(),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("failed to read {}: {e}", captions_path.display());
             }
+            Vec::new()
+        }
+    }
+}
+
+/// A way of moving through the images, one at a time.
+#[derive(Clone, Copy)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Either end of the images being browsed.
+#[derive(Clone, Copy)]
+enum Boundary {
+    Start,
+    End,
+}
+
+/// How to show how far through the current file the current image is.
+#[derive(Clone, Copy, Default)]
+enum ProgressDisplay {
+    #[default]
+    Hidden,
+    /// Up to ten dots, one for each tenth of the file up to the current image
+    Dots,
+    /// The number of the current image out of the number of images in the file
+    Fraction,
+}
+
+impl ProgressDisplay {
+    fn next(self) -> Self {
+        match self {
+            Self::Hidden => Self::Dots,
+            Self::Dots => Self::Fraction,
+            Self::Fraction => Self::Hidden,
+        }
+    }
+}
+
+/// How the size of the image is chosen before it's zoomed.
+#[derive(Clone, Copy, Default)]
+enum FitMode {
+    /// Scaled to fit entirely within the window
+    #[default]
+    Fit,
+    /// Scaled to cover the whole window, cropping whatever overflows it
+    Fill,
+    /// At its own size, with one image pixel per screen pixel
+    Actual,
+}
+
+impl FitMode {
+    fn next(self) -> Self {
+        match self {
+            Self::Fit => Self::Fill,
+            Self::Fill => Self::Actual,
+            Self::Actual => Self::Fit,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Fit => "Fit to window",
+            Self::Fill => "Fill window",
+            Self::Actual => "Actual size",
+        }
+    }
+}
+
+/// How far the current image is zoomed in and panned.
+#[derive(Clone, Copy)]
+struct Zoom {
+    /// The factor to scale the image by, on top of fitting it to the window
+    scale: f32,
+    /// How far the centre of the image is moved from the centre of the window, in pixels
+    offset_x: f32,
+    offset_y: f32,
+}
+
+impl Default for Zoom {
+    fn default() -> Self {
+        Self {
+            scale: MIN_ZOOM,
+            offset_x: 0.0,
+            offset_y: 0.0,
+        }
+    }
+}
+
+impl Zoom {
+    /// Limits panning so that some of an image which is drawn at `width` x `height` stays on
+    /// screen.
+    fn clamp_offset(&mut self, width: i32, height: i32, screen_width: i32, screen_height: i32) {
+        let max_offset = |size: i32, screen_size: i32| {
+            let visible = MIN_VISIBLE_WHILE_PANNING.min(size as f32);
+            ((screen_size + size) as f32 / 2.0 - visible).max(0.0)
+        };
+
+        let max_x = max_offset(width, screen_width);
+        let max_y = max_offset(height, screen_height);
+        self.offset_x = self.offset_x.clamp(-max_x, max_x);
+        self.offset_y = self.offset_y.clamp(-max_y, max_y);
+    }
+}
+
+/// How fast an image is being panned.
+#[derive(Clone, Copy)]
+struct Motion {
+    /// The speed in each direction, in pixels per second
+    velocity_x: f32,
+    velocity_y: f32,
+    /// When the image was last moved
+    moved_at: Instant,
+}
+
+impl Motion {
+    fn speed(&self) -> f32 {
+        self.velocity_x.hypot(self.velocity_y)
+    }
+}
+
+/// Where and how an image was drawn, so that it can be drawn the same way again.
+#[derive(Clone)]
+pub struct DrawnImage {
+    image: Image,
+    src: Rect,
+    /// Where the image was drawn, relative to `center` before rotating
+    dst: Rect,
+    center: (f32, f32),
+    quarter_turns: u8,
+    paint: Paint,
+}
+
+impl DrawnImage {
+    /// Draws the image with its opacity scaled by `alpha`, from 0 to 1.
+    pub fn draw(&self, alpha: f32, canvas: &skia_safe::Canvas) {
+        let mut paint = self.paint.clone();
+        paint.set_alpha_f(alpha);
+
+        canvas.save();
+        canvas.translate(self.center);
+        canvas.rotate(90.0 * self.quarter_turns as f32, None);
+        canvas.draw_image_rect_with_sampling_options(
+            &self.image,
+            Some((&self.src, SrcRectConstraint::Fast)),
+            self.dst,
+            SamplingOptions {
+                max_aniso: 0,
+                use_cubic: false,
+                cubic: skia_safe::CubicResampler { b: 0.0, c: 0.0 },
+                filter: skia_safe::FilterMode::Linear,
+                mipmap: skia_safe::MipmapMode::None,
+            },
+            &paint,
+        );
+        canvas.restore();
+    }
+}
+
+/// Returns how to draw `image` as large as it fits in the middle of a screen, the way the viewer
+/// shows it before it's zoomed, panned or rotated.
+pub fn fit_to_screen(image: Image, screen_width: i32, screen_height: i32) -> DrawnImage {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let scale = (screen_width as f32 / width).min(screen_height as f32 / height);
+    let (width, height) = ((width * scale) as i32, (height * scale) as i32);
+
+    DrawnImage {
+        src: Rect::from_iwh(image.width(), image.height()),
+        dst: Rect {
+            left: -width as f32 / 2.0,
+            top: -height as f32 / 2.0,
+            right: width as f32 / 2.0,
+            bottom: height as f32 / 2.0,
+        },
+        image,
+        center: (screen_width as f32 / 2.0, screen_height as f32 / 2.0),
+        quarter_turns: 0,
+        paint: Paint::default(),
+    }
+}
+
+/// The decoded frames of an image. Still images have a single frame.
+struct CurrentImage {
+    frames: Vec<Frame>,
+    /// The index into `frames` of the frame to display
+    frame_index: usize,
+    /// When the current frame was first displayed
+    shown_at: Instant,
+    /// Whether the frames were decoded at reduced resolution, as a preview
+    is_preview: bool,
+    /// The frames uploaded to the GPU, by their index, once they've been drawn. They're reused
+    /// for as long as the image is shown, rather than being uploaded for every draw.
+    textures: Vec<Option<Image>>,
+    /// For images which are too large to decode whole, whose only frame is an overview of them
+    tiles: Option<Tiles>,
+}
+
+/// The parts of an image which is too large to decode whole that have been decoded at full
+/// size. The image is drawn from an overview at reduced resolution, with these on top of it.
+struct Tiles {
+    /// The size of the whole image
+    size: ISize,
+    /// The part of the image last decoded at full size, and its pixels, which are uploaded to
+    /// the GPU once they're drawn
+    region: Option<(IRect, Image)>,
+    /// The part of the image being decoded at full size, until it's decoded
+    pending: Option<IRect>,
+    /// Whether decoding part of the image failed, in which case only the overview is drawn
+    failed: bool,
+}
+
+impl CurrentImage {
+    fn new(frames: Vec<Frame>, is_preview: bool) -> Self {
+        Self {
+            textures: vec![None; frames.len()],
+            frames,
+            frame_index: 0,
+            shown_at: Instant::now(),
+            is_preview,
+            tiles: None,
+        }
+    }
+
+    /// Returns an image which is too large to decode whole, of `size`, drawn from `overview`.
+    fn tiled(overview: DecodedImage, size: ISize) -> Self {
+        let frame = Frame {
+            image: overview,
+            duration: Duration::ZERO,
+        };
+
+        Self {
+            tiles: Some(Tiles {
+                size,
+                region: None,
+                pending: None,
+                failed: false,
+            }),
+            ..Self::new(vec![frame], false)
+        }
+    }
+
+    /// Returns the size of the image, which is larger than its frame for tiled images.
+    fn size(&self) -> ISize {
+        match &self.tiles {
+            Some(tiles) => tiles.size,
+            None => self.current_frame().info.dimensions(),
+        }
+    }
+
+    fn current_frame(&self) -> &DecodedImage {
+        &self.frames[self.frame_index].image
+    }
+
+    /// Returns when to move on to the next frame, or `None` if the image isn't animated.
+    fn next_frame_at(&self) -> Option<Instant> {
+        if self.frames.len() < 2 {
+            return None;
+        }
+
+        let duration = self.frames[self.frame_index].duration;
 
-            self.paths.index += 1;
-            self.current_file.file = FileContainer::open(&self.paths.data[self.paths.index]);
-            self.current_file.index = 0;
+        // Like browsers, treat very short durations as missing. GIFs often have them despite
+        // being made to play slower.
+        let duration = if duration <= Duration::from_millis(10) {
+            DEFAULT_FRAME_DURATION
         } else {
-            self.current_file.index += 1;
+            duration
+        };
+
+        Some(self.shown_at + duration)
+    }
+
+    /// Returns the current frame backed by a GPU texture, uploading it the first time it's
+    /// drawn. If it can't be uploaded, the decoded image is drawn from instead.
+    fn current_texture(&mut self, context: &mut DirectContext) -> Image {
+        let image = &self.frames[self.frame_index].image.image;
+
+        self.textures[self.frame_index]
+            .get_or_insert_with(|| {
+                image
+                    .new_texture_image(context, Mipmapped::No)
+                    .unwrap_or_else(|| image.clone())
+            })
+            .clone()
+    }
+
+    /// Moves on to the next frame, looping back to the first after the last, once the current
+    /// frame has been displayed for long enough.
+    fn advance(&mut self) {
+        let now = Instant::now();
+        if self
+            .next_frame_at()
+            .is_some_and(|next_frame_at| now >= next_frame_at)
+        {
+            self.frame_index = (self.frame_index + 1) % self.frames.len();
+            self.shown_at = now;
         }
     }
+}
 
-    pub fn previous_image(&mut self) {
-        if self.current_file.index == 0 {
-            if self.paths.index == 0 {
-                return;
+/// An image to move to within a file.
+#[derive(Clone, Copy)]
+enum Target {
+    /// The image at an index, or the last image if the file is too short
+    Index(usize),
+    Last,
+    /// The image that the file was last left at
+    LastLeftAt,
+}
+
+impl Target {
+    fn index_in(self, file: &CurrentFile) -> usize {
+        match self {
+            Self::Index(index) => index.min(file.last_index()),
+            Self::Last => file.last_index(),
+            Self::LastLeftAt => file.index,
+        }
+    }
+}
+
+struct PeekedCover {
+    /// The path of the file that this is the cover of
+    path: PathBuf,
+    /// The decoded cover, or `None` if the file has no cover which can be displayed
+    image: Option<DecodedImage>,
+}
+
+impl Screen {
+    pub fn new(paths: Vec<PathBuf>, config: Config, cache: ImageCache, decoder: Decoder) -> Self {
+        // Start from the first file which has something to display
+        let first_file = paths.iter().enumerate().find_map(|(i, path)| {
+            let current_file = CurrentFile::open(path);
+            (!current_file.is_empty()).then_some((i, current_file))
+        });
+        let (index, current_file) = match first_file {
+            Some((index, current_file)) => (index, Some(current_file)),
+            None => (0, None),
+        };
+
+        let checked_position = current_file
+            .as_ref()
+            .map(|current_file| (index, current_file.index));
+
+        Self {
+            preloader: Preloader::new(cache.clone(), config.tile_above_pixels),
+            slideshow_interval: config.slideshow_interval,
+            config,
+            current_file,
+            paths: Paths { data: paths, index },
+            reading_positions: HashMap::new(),
+            progress_display: ProgressDisplay::default(),
+            show_captions: false,
+            show_info: false,
+            show_view_state: false,
+            show_decoding: false,
+            loop_within_file: false,
+            sharpen_monochrome: false,
+            toast: None,
+            last_error: None,
+            file_switched_at: None,
+            peek_started_at: None,
+            peeked_cover: None,
+            pending_cover: None,
+            current_image: None,
+            last_drawn: None,
+            fading_out: None,
+            fit_mode: FitMode::default(),
+            zoom: Zoom::default(),
+            slideshow_image_shown_at: None,
+            slideshow_waiting: false,
+            typed_number: None,
+            clipboard: None,
+            boundary_hit: None,
+            marked: HashSet::new(),
+            quarter_turns: 0,
+            kinetic_panning: true,
+            drag: None,
+            coasting: None,
+            cache,
+            decoder,
+            pending_decode: None,
+            direction: None,
+            checked_position,
+        }
+    }
+
+    /// Forgets the state of the current image, before navigating away from it.
+    fn leave_image(&mut self) {
+        self.last_error = None;
+        self.current_image = None;
+        self.pending_decode = None;
+
+        let last_drawn = self.last_drawn.take();
+        if let Transition::Fade = self.config.transition {
+            self.fading_out = last_drawn.map(|drawn| (drawn, Instant::now()));
+        }
+        self.zoom = Zoom::default();
+        self.quarter_turns = 0;
+        self.drag = None;
+        self.coasting = None;
+
+        // Moving by hand restarts the wait for the slideshow to move on
+        if let Some(shown_at) = &mut self.slideshow_image_shown_at {
+            match self.config.slideshow_timer {
+                SlideshowTimer::Switch => *shown_at = Instant::now(),
+                SlideshowTimer::Display => self.slideshow_waiting = true,
             }
+        }
+    }
 
-            self.paths.index -= 1;
-            self.current_file.file = FileContainer::open(&self.paths.data[self.paths.index]);
-            self.current_file.index = self.current_file.file.len() - 1;
+    /// Moves to the next image, returning whether there was one to move to.
+    pub fn next_image(&mut self) -> bool {
+        let Some(current_file) = &self.current_file else {
+            return false;
+        };
+
+        let (file_index, target) = if current_file.index < current_file.last_index() {
+            (self.paths.index, Target::Index(current_file.index + 1))
+        } else if self.loop_within_file {
+            (self.paths.index, Target::Index(0))
+        } else if self.paths.index < self.paths.data.len() - 1 {
+            (self.paths.index + 1, Target::Index(0))
+        } else if self.config.wrap {
+            (0, Target::Index(0))
         } else {
-            self.current_file.index -= 1;
-        }
+            self.boundary_hit = Some((Boundary::End, Instant::now()));
+            return false;
+        };
+
+        self.direction = Some(Direction::Forward);
+        self.go_to(file_index, target)
+    }
+
+    /// Moves to the previous image, returning whether there was one to move to.
+    pub fn previous_image(&mut self) -> bool {
+        let Some(current_file) = &self.current_file else {
+            return false;
+        };
+
+        let (file_index, target) = if current_file.index > 0 {
+            (self.paths.index, Target::Index(current_file.index - 1))
+        } else if self.loop_within_file {
+            (self.paths.index, Target::Last)
+        } else if self.paths.index > 0 {
+            (self.paths.index - 1, Target::Last)
+        } else if self.config.wrap {
+            (self.paths.data.len() - 1, Target::Last)
+        } else {
+            self.boundary_hit = Some((Boundary::Start, Instant::now()));
+            return false;
+        };
+
+        self.direction = Some(Direction::Backward);
+        self.go_to(file_index, target)
     }
 
     pub fn next_file(&mut self) {
-        if self.paths.index == 0 {
+        if self.current_file.is_none() || self.paths.data.len() == 1 {
             return;
         }
 
-        self.paths.index -= 1;
-        self.current_file.file = FileContainer::open(&self.paths.data[self.paths.index]);
-        self.current_file.index = 0;
+        let file_index = if self.paths.index > 0 {
+            self.paths.index - 1
+        } else if self.config.wrap {
+            self.paths.data.len() - 1
+        } else {
+            return;
+        };
+
+        if self.go_to(file_index, Target::LastLeftAt) {
+            self.file_switched_at = Some(Instant::now());
+        }
     }
 
     pub fn previous_file(&mut self) {
-        if self.paths.index == self.paths.data.len() - 1 {
+        if self.current_file.is_none() || self.paths.data.len() == 1 {
             return;
         }
 
-        self.paths.index += 1;
-        self.current_file.file = FileContainer::open(&self.paths.data[self.paths.index]);
-        self.current_file.index = 0;
+        let file_index = if self.paths.index < self.paths.data.len() - 1 {
+            self.paths.index + 1
+        } else if self.config.wrap {
+            0
+        } else {
+            return;
+        };
+
+        if self.go_to(file_index, Target::LastLeftAt) {
+            self.file_switched_at = Some(Instant::now());
+        }
     }
 
-    pub fn toggle_progress_display(&mut self) {
-        self.show_progress = !self.show_progress;
+    /// Moves to the start of the next chapter in the current file, returning whether there was
+    /// one to move to.
+    pub fn next_chapter(&mut self) -> bool {
+        let Some(current_file) = &self.current_file else {
+            return false;
+        };
+
+        let Some(index) = current_file
+            .chapters
+            .iter()
+            .map(|chapter| chapter.index)
+            .find(|&index| index > current_file.index)
+        else {
+            self.boundary_hit = Some((Boundary::End, Instant::now()));
+            return false;
+        };
+
+        self.direction = Some(Direction::Forward);
+        self.go_to(self.paths.index, Target::Index(index))
     }
-}
 
-pub fn render_frame(
-    screen_width: i32,
-    screen_height: i32,
-    state: &mut Screen,
-    canvas: &skia_safe::Canvas,
-) {
-    let image_bytes = state.current_image_bytes();
+    /// Moves to the start of the current chapter, or to the start of the one before it if
+    /// already there, returning whether there was one to move to.
+    pub fn previous_chapter(&mut self) -> bool {
+        let Some(current_file) = &self.current_file else {
+            return false;
+        };
 
-    let mut c = Cursor::new(&image_bytes);
+        let Some(index) = current_file
+            .chapters
+            .iter()
+            .rev()
+            .map(|chapter| chapter.index)
+            .find(|&index| index < current_file.index)
+        else {
+            self.boundary_hit = Some((Boundary::Start, Instant::now()));
+            return false;
+        };
 
-    let mut codec = if image_bytes.starts_with(b"\xFF\xD8\xFF") {
-        jpeg_decoder::decode_stream(&mut c).unwrap()
-    } else if image_bytes.starts_with(b"\x89PNG\x0D\x0A\x1A\x0A") {
-        png_decoder::decode_stream(&mut c).unwrap()
-    } else if image_bytes.len() > b"RIFF\0\0\0\0WEBPVP".len()
-        && image_bytes.starts_with(b"RIFF")
-        && &image_bytes[8..][..6] == b"WEBPVP"
-    {
-        webp_decoder::decode_stream(&mut c).unwrap()
-    } else {
-        panic!("unsupported file type");
-    };
+        self.direction = Some(Direction::Backward);
+        self.go_to(self.paths.index, Target::Index(index))
+    }
 
-    let image = codec.get_image(codec.info(), None).unwrap();
-    let info = codec.info();
+    /// Moves to the image at `index` in the current file, or the last image if the file is too
+    /// short.
+    pub fn jump_to(&mut self, index: usize) {
+        self.go_to(self.paths.index, Target::Index(index));
+    }
 
-    let ISize {
-        mut width,
-        mut height,
-    } = info.dimensions();
+    pub fn is_typing_number(&self) -> bool {
+        self.typed_number.is_some()
+    }
 
-    // Determine the scaling factor based on the window dimensions
-    let scale_x = screen_width as f32 / width as f32;
-    let scale_y = screen_height as f32 / height as f32;
+    /// Adds `digit` to the end of the image number being typed.
+    pub fn type_digit(&mut self, digit: char) {
+        self.typed_number.get_or_insert_default().push(digit);
+    }
 
-    // Use the smaller scaling factor to fit within the window
-    let scale = scale_x.min(scale_y);
+    pub fn cancel_typed_number(&mut self) {
+        self.typed_number = None;
+    }
 
-    width = (width as f32 * scale) as i32;
-    height = (height as f32 * scale) as i32;
+    /// Jumps to the image with the number which was typed, counting from 1.
+    pub fn submit_typed_number(&mut self) {
+        let Some(typed_number) = self.typed_number.take() else {
+            return;
+        };
 
-    let x_offset = (screen_width - width) / 2;
-    let y_offset = (screen_height - height) / 2;
+        // Numbers too large to parse are past the end of any file
+        let number: usize = typed_number.parse().unwrap_or(usize::MAX);
+        self.jump_to(number.saturating_sub(1));
+    }
 
-    canvas.draw_image_rect_with_sampling_options(
-        image,
-        None,
-        Rect {
-            left: x_offset as f32,
-            top: y_offset as f32,
-            right: (screen_width - x_offset) as f32,
-            bottom: (screen_height - y_offset) as f32,
-        },
-        SamplingOptions {
-            max_aniso: 0,
-            use_cubic: false,
-            cubic: skia_safe::CubicResampler { b: 0.0, c: 0.0 },
-            filter: skia_safe::FilterMode::Linear,
-            mipmap: skia_safe::MipmapMode::None,
-        },
-        &Paint::default(),
-    );
+    /// Adds files after the ones already being browsed, and moves to the first of them.
+    pub fn add_files(&mut self, paths: Vec<PathBuf>) {
+        if paths.is_empty() {
+            return;
+        }
 
-    if state.show_progress {
-        render_progress(
-            state.current_file.index,
-            state.current_file.file.len(),
-            canvas,
-        );
+        self.leave_image();
+
+        let first_new = self.paths.data.len();
+        self.paths.data.extend(paths);
+        self.switch_file(first_new);
+        self.file_switched_at = Some(Instant::now());
     }
-}
 
-fn render_progress(index: usize, len: usize, canvas: &skia_safe::Canvas) {
-    let progress = index * 10 / len; // out of 10
+    /// Removes the current file from the ones being browsed, and moves to the file after it, or
+    /// the one before it if it was the last. The file is only moved to the trash if deleting is
+    /// allowed.
+    pub fn remove_file(&mut self) {
+        if self.current_file.is_none() {
+            return;
+        }
 
-    let mut paint = Paint::default();
-    paint.set_color(Color::WHITE);
+        self.leave_image();
 
-    const RADIUS: f32 = 16.0;
-    const SPACING: f32 = 8.0;
+        let removed_index = self.paths.index;
+        let removed = self.paths.data.remove(removed_index);
+        let name = removed.file_name().unwrap_or_default().to_string_lossy();
+        if self.config.allow_delete {
+            match trash::delete(&removed) {
+                Ok(()) => self.show_toast(format!("Moved {name} to the trash")),
+                Err(e) => {
+                    eprintln!("failed to move {} to the trash: {e}", removed.display());
+                    self.show_toast(format!("Removed {name}, but couldn't move it to the trash"));
+                }
+            }
+        } else {
+            self.show_toast(format!("Removed {name}"));
+        }
 
-    for i in 0..progress {
-        let top_offset = (2.0 * RADIUS + SPACING) * (1 + i / 3) as f32;
-        let left_offset = (2.0 * RADIUS + SPACING) * (1 + i % 3) as f32;
-        canvas.draw_circle((left_offset, top_offset), RADIUS, &paint);
+        self.reading_positions.remove(&removed);
+        self.peek_started_at = None;
+        self.peeked_cover = None;
+
+        // The removed file's position isn't worth remembering
+        self.current_file = None;
+        if self.paths.data.is_empty() {
+            self.paths.index = 0;
+            return;
+        }
+
+        self.switch_file(removed_index.min(self.paths.data.len() - 1));
+    }
+
+    /// Moves to the first image of the current file.
+    pub fn first_image(&mut self) {
+        self.go_to(self.paths.index, Target::Index(0));
+    }
+
+    /// Moves to the last image of the current file.
+    pub fn last_image(&mut self) {
+        self.go_to(self.paths.index, Target::Last);
+    }
+
+    /// Moves to the first image of the first file.
+    pub fn first_image_overall(&mut self) {
+        self.go_to(0, Target::Index(0));
+    }
+
+    /// Moves to the last image of the last file.
+    pub fn last_image_overall(&mut self) {
+        self.go_to(self.paths.data.len().saturating_sub(1), Target::Last);
+    }
+
+    /// Moves to `target` in the file at `file_index`, returning whether that's a different image
+    /// from the current one. The current image is only left behind if it is.
+    fn go_to(&mut self, file_index: usize, target: Target) -> bool {
+        let Some(current_file) = &mut self.current_file else {
+            return false;
+        };
+
+        if file_index == self.paths.index {
+            let index = target.index_in(current_file);
+            if index == current_file.index {
+                return false;
+            }
+
+            current_file.index = index;
+            self.leave_image();
+            return true;
+        }
+
+        self.leave_image();
+        let current_file = self.switch_file(file_index);
+        current_file.index = target.index_in(current_file);
+
+        true
+    }
+
+    /// Opens the file at `file_index` in place of the current one, remembering which image the
+    /// current one was left at. Returns the opened file, at the image it was last left at.
+    fn switch_file(&mut self, file_index: usize) -> &mut CurrentFile {
+        if let Some(current_file) = &self.current_file {
+            self.reading_positions.insert(
+                self.paths.data[self.paths.index].clone(),
+                current_file.index,
+            );
+        }
+
+        // The file to peek at changes along with the current file
+        self.peek_started_at = None;
+
+        self.paths.index = file_index;
+        let current_file = self
+            .current_file
+            .insert(CurrentFile::open(&self.paths.data[file_index]));
+        if let Some(&index) = self.reading_positions.get(&self.paths.data[file_index]) {
+            current_file.index = index.min(current_file.last_index());
+        }
+
+        current_file
+    }
+
+    /// Returns the index of the current file and the index of the current image within it.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        let current_file = self.current_file.as_ref()?;
+
+        Some((self.paths.index, current_file.index))
+    }
+
+    /// Returns the frames of the current image at full size, if they're cached.
+    fn cached_frames(&self) -> Option<Vec<Frame>> {
+        let (file_index, index) = self.position()?;
+
+        self.cache.get(&self.paths.data[file_index], index)
+    }
+
+    /// Starts decoding the current image on the decoder's thread, unless it's already being
+    /// decoded.
+    fn request_decode(&mut self, is_preview: bool) {
+        let Some((file_index, index)) = self.position() else {
+            return;
+        };
+        let Some(Ok(file)) = self
+            .current_file
+            .as_ref()
+            .map(|current_file| &current_file.file)
+        else {
+            return;
+        };
+
+        let request = Request {
+            path: self.paths.data[file_index].clone(),
+            file: file.clone(),
+            index,
+            preview_scale: is_preview.then_some(FILE_PREVIEW_SCALE),
+            tile_above_pixels: self.config.tile_above_pixels,
+        };
+        if self.pending_decode.as_ref() == Some(&request) {
+            return;
+        }
+
+        self.decoder.decode(request.clone());
+        self.pending_decode = Some(request);
+    }
+
+    /// Shows an image decoded on the decoder's thread, if it's still the one waited for.
+    /// Returns whether it was shown, so that the screen needs to be redrawn.
+    pub fn on_decoded(&mut self, decoded: Decoded) -> bool {
+        let (request, image) = match decoded {
+            Decoded::Image { request, image } => (request, image),
+            Decoded::Cover { path, image } => return self.on_cover_decoded(path, image),
+            Decoded::Region {
+                path,
+                index,
+                region,
+                image,
+            } => return self.on_region_decoded(&path, index, region, image),
+        };
+
+        // Navigation has moved on from any other image
+        if self.pending_decode.as_ref() != Some(&request) {
+            return false;
+        }
+        self.pending_decode = None;
+
+        let is_preview = request.preview_scale.is_some();
+        match image {
+            Ok(Loaded::Frames(frames)) => {
+                if !is_preview {
+                    self.cache
+                        .insert(&request.path, request.index, frames.clone());
+
+                    // Navigation has settled once the full image is shown, so get ready for
+                    // the next move
+                    self.start_preloading();
+                }
+
+                self.current_image = Some(CurrentImage::new(frames, is_preview));
+            }
+            Ok(Loaded::Overview(overview, size)) => {
+                // Overviews aren't cached, since they'd be mistaken for the whole image
+                self.start_preloading();
+
+                self.current_image = Some(CurrentImage::tiled(overview, size));
+            }
+            Err(error) => self.last_error = Some(error),
+        }
+
+        true
+    }
+
+    /// Keeps part of the current image decoded at full size on the decoder's thread, if it's
+    /// still the part waited for. Returns whether it was kept, so that the screen needs to be
+    /// redrawn.
+    fn on_region_decoded(
+        &mut self,
+        path: &Path,
+        index: usize,
+        region: IRect,
+        image: Result<DecodedImage, LoadError>,
+    ) -> bool {
+        if self.position().is_none_or(|(file_index, current_index)| {
+            self.paths.data[file_index] != path || current_index != index
+        }) {
+            return false;
+        }
+        let Some(tiles) = self
+            .current_image
+            .as_mut()
+            .and_then(|current_image| current_image.tiles.as_mut())
+            .filter(|tiles| tiles.pending == Some(region))
+        else {
+            return false;
+        };
+        tiles.pending = None;
+
+        match image {
+            Ok(image) => tiles.region = Some((region, image.image)),
+            Err(e) => {
+                // The overview is still shown, so it's only reported once
+                eprintln!("failed to decode part of the image: {e}");
+                tiles.failed = true;
+            }
+        }
+
+        true
+    }
+
+    fn start_preloading(&self) {
+        self.preloader
+            .preload(self.images_to_preload(), self.spare_images_to_preload());
+    }
+
+    /// Returns the images to decode ahead of them being moved to, by the paths of the files
+    /// they're in and their indices in them. Once `next_image` or `previous_image` has been
+    /// used, several images the way it moved are returned, and none the other way, since
+    /// reading goes one way.
+    fn images_to_preload(&self) -> Vec<(PathBuf, usize)> {
+        let Some(current) = self.position() else {
+            return Vec::new();
+        };
+
+        let (ahead, behind) = match self.direction {
+            None => (1, 1),
+            Some(Direction::Forward) => (self.config.prefetch, 0),
+            Some(Direction::Backward) => (0, self.config.prefetch),
+        };
+
+        let mut images = Vec::new();
+        for (count, direction) in [(ahead, Direction::Forward), (behind, Direction::Backward)] {
+            let mut position = current;
+            for _ in 0..count {
+                // Short files which loop around run out of other images to preload
+                match self.step(position, direction) {
+                    Some(next) if next != current && !images.contains(&next) => {
+                        images.push(next);
+                        position = next;
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        images
+            .into_iter()
+            .map(|(file_index, index)| (self.paths.data[file_index].clone(), index))
+            .collect()
+    }
+
+    /// Returns every other image in the current file if it's short enough to decode whole,
+    /// nearest to the current image first, to decode once the images from `images_to_preload`
+    /// have been.
+    fn spare_images_to_preload(&self) -> Vec<(PathBuf, usize)> {
+        let (Some(max_images), Some((file_index, index)), Some(current_file)) = (
+            self.config.preload_file_under,
+            self.position(),
+            &self.current_file,
+        ) else {
+            return Vec::new();
+        };
+        if current_file.len() >= max_images {
+            return Vec::new();
+        }
+
+        let mut indices = (0..current_file.len())
+            .filter(|&i| i != index)
+            .collect::<Vec<_>>();
+        indices.sort_by_key(|i| i.abs_diff(index));
+
+        let path = &self.paths.data[file_index];
+        indices.into_iter().map(|i| (path.clone(), i)).collect()
+    }
+
+    /// Returns the position which `next_image` or `previous_image` would move to from the image
+    /// at `index` in the file at `file_index`, as far as that's known without opening any file
+    /// other than the current one.
+    fn step(
+        &self,
+        (file_index, index): (usize, usize),
+        direction: Direction,
+    ) -> Option<(usize, usize)> {
+        let current_file = self.current_file.as_ref()?;
+        // Such as the last index of the previous file
+        if file_index != self.paths.index {
+            return None;
+        }
+
+        let last_index = current_file.last_index();
+        match direction {
+            Direction::Forward if index < last_index => Some((file_index, index + 1)),
+            Direction::Forward if self.loop_within_file => Some((file_index, 0)),
+            Direction::Forward if file_index + 1 < self.paths.data.len() => {
+                Some((file_index + 1, 0))
+            }
+            Direction::Forward if self.config.wrap => Some((0, 0)),
+            Direction::Backward if index > 0 => Some((file_index, index - 1)),
+            Direction::Backward if self.loop_within_file => Some((file_index, last_index)),
+            _ => None,
+        }
+    }
+
+    /// Writes the path of the current file and the current position to stdout, separated by tabs.
+    /// Returns the position if it's changed since this was last called, or since the screen was
+    /// created.
+    fn take_changed_position(&mut self) -> Option<(usize, usize)> {
+        let position = self.position();
+        if position == self.checked_position {
+            return None;
+        }
+
+        self.checked_position = position;
+        position
+    }
+
+    pub fn print_position(&self) {
+        let Some((file_index, image_index)) = self.position() else {
+            return;
+        };
+
+        println!(
+            "{}\t{file_index}\t{image_index}",
+            self.paths.data[file_index].display()
+        );
+    }
+
+    /// Starts showing the cover of the next file on top of the current image.
+    pub fn start_peek(&mut self) {
+        if self.paths.index + 1 < self.paths.data.len() {
+            self.peek_started_at = Some(Instant::now());
+        }
+    }
+
+    /// Starts decoding the cover of the file at `path` for peeking at, to fit in `size`, unless
+    /// it's already being decoded.
+    fn request_cover(&mut self, path: &Path, size: ISize) {
+        if self.pending_cover.as_deref() == Some(path) {
+            return;
+        }
+
+        self.decoder.decode_cover(
+            path.to_path_buf(),
+            self.config.cover,
+            self.cache.clone(),
+            size,
+        );
+        self.pending_cover = Some(path.to_path_buf());
+    }
+
+    /// Keeps a cover decoded on the decoder's thread, if it's still the one waited for. Returns
+    /// whether it was kept, so that the screen needs to be redrawn.
+    fn on_cover_decoded(&mut self, path: PathBuf, image: Option<DecodedImage>) -> bool {
+        if self.pending_cover.as_ref() != Some(&path) {
+            return false;
+        }
+        self.pending_cover = None;
+
+        self.peeked_cover = Some(PeekedCover { path, image });
+
+        self.peeking()
+    }
+
+    pub fn stop_peek(&mut self) {
+        self.peek_started_at = None;
+    }
+
+    fn peeking(&self) -> bool {
+        self.peek_started_at
+            .is_some_and(|started_at| started_at.elapsed() < PEEK_DURATION)
+    }
+
+    /// Zooms the current image in by `factor`, or out if it's less than 1, keeping the point
+    /// under the cursor at (`x`, `y`) in place.
+    pub fn zoom_at(&mut self, factor: f32, x: f32, y: f32, screen_width: i32, screen_height: i32) {
+        let scale = (self.zoom.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        if scale == MIN_ZOOM {
+            self.zoom = Zoom::default();
+            return;
+        }
+
+        let factor = scale / self.zoom.scale;
+        let center_x = screen_width as f32 / 2.0 + self.zoom.offset_x;
+        let center_y = screen_height as f32 / 2.0 + self.zoom.offset_y;
+
+        self.zoom.scale = scale;
+        self.zoom.offset_x += (center_x - x) * (factor - 1.0);
+        self.zoom.offset_y += (center_y - y) * (factor - 1.0);
+    }
+
+    /// Moves the current image by (`dx`, `dy`) pixels.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.zoom.offset_x += dx;
+        self.zoom.offset_y += dy;
+
+        // The speed is smoothed over the last few moves, since the time between them is uneven
+        let now = Instant::now();
+        self.drag = Some(match self.drag {
+            Some(drag) if now > drag.moved_at => {
+                let elapsed = (now - drag.moved_at).as_secs_f32();
+                Motion {
+                    velocity_x: 0.5 * drag.velocity_x + 0.5 * dx / elapsed,
+                    velocity_y: 0.5 * drag.velocity_y + 0.5 * dy / elapsed,
+                    moved_at: now,
+                }
+            }
+            _ => Motion {
+                velocity_x: 0.0,
+                velocity_y: 0.0,
+                moved_at: now,
+            },
+        });
+    }
+
+    /// Starts dragging the current image, which stops it if it's still moving from being flung.
+    pub fn start_pan(&mut self) {
+        self.drag = None;
+        self.coasting = None;
+    }
+
+    /// Stops dragging the current image, which keeps moving at the speed it was dragged at if
+    /// kinetic panning is on, until it slows to a stop.
+    pub fn end_pan(&mut self) {
+        let Some(drag) = self.drag.take() else {
+            return;
+        };
+
+        if self.kinetic_panning
+            && drag.moved_at.elapsed() < FLING_WINDOW
+            && drag.speed() >= FLING_MIN_SPEED
+        {
+            self.coasting = Some(Motion {
+                moved_at: Instant::now(),
+                ..drag
+            });
+        }
+    }
+
+    /// Moves a flung image on by as far as it's travelled since it was last moved, slowing it
+    /// down as it goes.
+    fn coast(&mut self) {
+        let Some(motion) = &mut self.coasting else {
+            return;
+        };
+
+        let now = Instant::now();
+        let elapsed = (now - motion.moved_at).as_secs_f32();
+        self.zoom.offset_x += motion.velocity_x * elapsed;
+        self.zoom.offset_y += motion.velocity_y * elapsed;
+
+        let decay = FLING_FRICTION.powf(elapsed);
+        motion.velocity_x *= decay;
+        motion.velocity_y *= decay;
+        motion.moved_at = now;
+
+        if motion.speed() < FLING_MIN_SPEED {
+            self.coasting = None;
+        }
+    }
+
+    pub fn toggle_kinetic_panning(&mut self) {
+        self.kinetic_panning = !self.kinetic_panning;
+        self.coasting = None;
+
+        self.show_toast(if self.kinetic_panning {
+            "Kinetic panning: on"
+        } else {
+            "Kinetic panning: off"
+        });
+    }
+
+    /// Switches to the next way of sizing images.
+    pub fn cycle_fit_mode(&mut self) {
+        self.fit_mode = self.fit_mode.next();
+        self.zoom = Zoom::default();
+
+        self.show_toast(self.fit_mode.description());
+    }
+
+    pub fn reset_zoom(&mut self) {
+        self.zoom = Zoom::default();
+    }
+
+    /// Rotates the current image a quarter turn clockwise.
+    pub fn rotate_cw(&mut self) {
+        self.quarter_turns = (self.quarter_turns + 1) % 4;
+    }
+
+    /// Rotates the current image a quarter turn counterclockwise.
+    pub fn rotate_ccw(&mut self) {
+        self.quarter_turns = (self.quarter_turns + 3) % 4;
+    }
+
+    /// Attempts to load the current image again after it previously failed, opening its file
+    /// again too if that failed.
+    pub fn retry(&mut self) {
+        self.last_error = None;
+
+        if let Some(current_file) = &mut self.current_file
+            && current_file.file.is_err()
+        {
+            current_file.file =
+                MappedContainer::open(&self.paths.data[self.paths.index]).map(Arc::new);
+        }
+    }
+
+    /// Switches to the next way of showing how far through the current file the current image
+    /// is.
+    pub fn cycle_progress_display(&mut self) {
+        self.progress_display = self.progress_display.next();
+    }
+
+    pub fn toggle_captions(&mut self) {
+        self.show_captions = !self.show_captions;
+    }
+
+    pub fn toggle_info(&mut self) {
+        self.show_info = !self.show_info;
+    }
+
+    pub fn toggle_decoding_indicator(&mut self) {
+        self.show_decoding = !self.show_decoding;
+    }
+
+    /// Returns whether any image is being decoded in the background, whether it's been asked
+    /// for or is being preloaded.
+    fn is_decoding(&self) -> bool {
+        self.pending_decode.is_some()
+            || self.pending_cover.is_some()
+            || self
+                .current_image
+                .as_ref()
+                .and_then(|current_image| current_image.tiles.as_ref())
+                .is_some_and(|tiles| tiles.pending.is_some())
+            || self.preloader.is_busy()
+    }
+
+    pub fn toggle_view_state(&mut self) {
+        self.show_view_state = !self.show_view_state;
+    }
+
+    /// Returns the lines of text describing how the current image is shown, for the info
+    /// overlay.
+    fn view_state_lines(&self) -> Vec<String> {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+
+        vec![
+            format!("Zoom: {:.2}\u{d7}", self.zoom.scale),
+            format!("Pan: {:.0}, {:.0}", self.zoom.offset_x, self.zoom.offset_y),
+            format!("Rotation: {}\u{b0}", 90 * u32::from(self.quarter_turns)),
+            self.fit_mode.description().to_string(),
+            format!(
+                "Sharpen monochrome images: {}",
+                on_off(self.sharpen_monochrome)
+            ),
+        ]
+    }
+
+    /// Returns the lines of text describing the current file and image, for the info overlay.
+    fn info_lines(&self) -> Vec<String> {
+        let Some(current_file) = &self.current_file else {
+            return Vec::new();
+        };
+
+        let mut lines = vec![self.paths.data[self.paths.index].display().to_string()];
+        if current_file.is_empty() {
+            return lines;
+        }
+
+        lines.push(format!(
+            "Image {} / {}",
+            current_file.index + 1,
+            current_file.len()
+        ));
+        // Taken from the decoded image, rather than by parsing its header from the file, which
+        // faults if the file's drive is disconnected. Previews are smaller, so nothing is shown
+        // until the full size image replaces them.
+        if let Some(ISize { width, height }) = self
+            .current_image
+            .as_ref()
+            .filter(|current_image| !current_image.is_preview)
+            .map(CurrentImage::size)
+        {
+            lines.push(format!("{width} \u{d7} {height}"));
+        }
+
+        lines
+    }
+
+    pub fn toggle_loop_within_file(&mut self) {
+        self.loop_within_file = !self.loop_within_file;
+
+        self.show_toast(if self.loop_within_file {
+            "Loop within file: on"
+        } else {
+            "Loop within file: off"
+        });
+    }
+
+    pub fn toggle_sharpen_monochrome(&mut self) {
+        self.sharpen_monochrome = !self.sharpen_monochrome;
+
+        self.show_toast(if self.sharpen_monochrome {
+            "Sharpen monochrome images: on"
+        } else {
+            "Sharpen monochrome images: off"
+        });
+    }
+
+    pub fn toggle_slideshow(&mut self) {
+        self.slideshow_image_shown_at = match self.slideshow_image_shown_at {
+            Some(_) => None,
+            None => Some(Instant::now()),
+        };
+        self.slideshow_waiting = self.slideshow_image_shown_at.is_some()
+            && matches!(self.config.slideshow_timer, SlideshowTimer::Display)
+            && self
+                .current_image
+                .as_ref()
+                .is_none_or(|current_image| current_image.is_preview);
+
+        self.show_toast(if self.slideshow_image_shown_at.is_some() {
+            "Slideshow: on"
+        } else {
+            "Slideshow: off"
+        });
+    }
+
+    /// Returns how far through showing the current image the slideshow is, from 0 to 1, or
+    /// `None` if a slideshow isn't running.
+    fn slideshow_progress(&self) -> Option<f32> {
+        let shown_at = self.slideshow_image_shown_at?;
+        if self.slideshow_waiting {
+            return Some(0.0);
+        }
+
+        Some((shown_at.elapsed().as_secs_f32() / self.slideshow_interval.as_secs_f32()).min(1.0))
+    }
+
+    fn next_slide_at(&self) -> Option<Instant> {
+        self.slideshow_image_shown_at
+            .filter(|_| !self.slideshow_waiting)
+            .map(|shown_at| shown_at + self.slideshow_interval)
+    }
+
+    /// Halves how long each image is shown for in the slideshow.
+    pub fn speed_up_slideshow(&mut self) {
+        self.set_slideshow_interval(self.slideshow_interval / 2);
+    }
+
+    /// Doubles how long each image is shown for in the slideshow.
+    pub fn slow_down_slideshow(&mut self) {
+        self.set_slideshow_interval(self.slideshow_interval.saturating_mul(2));
+    }
+
+    /// Changes how long each image is shown for, including the current one, which moves on once
+    /// it's been shown for the new interval.
+    fn set_slideshow_interval(&mut self, interval: Duration) {
+        self.slideshow_interval = interval.clamp(MIN_SLIDESHOW_INTERVAL, MAX_SLIDESHOW_INTERVAL);
+
+        self.show_toast(format!(
+            "Slideshow interval: {}s",
+            self.slideshow_interval.as_secs_f64()
+        ));
+    }
+
+    /// Starts timing how long the current image has been shown for, if the slideshow was
+    /// waiting for it to be drawn.
+    fn start_slideshow_timer(&mut self) {
+        if !self.slideshow_waiting {
+            return;
+        }
+
+        self.slideshow_waiting = false;
+        if let Some(shown_at) = &mut self.slideshow_image_shown_at {
+            *shown_at = Instant::now();
+        }
+    }
+
+    /// Moves on to the next image in the slideshow once the current one has been shown for
+    /// long enough. The slideshow stops after the last image.
+    fn advance_slideshow(&mut self) {
+        if self
+            .next_slide_at()
+            .is_none_or(|next_slide_at| Instant::now() < next_slide_at)
+        {
+            return;
+        }
+
+        if !self.next_image() {
+            self.slideshow_image_shown_at = None;
+            self.show_toast("Slideshow finished");
+        }
+    }
+
+    /// Marks the current image, or unmarks it if it's already marked.
+    pub fn toggle_mark(&mut self) {
+        let Some((file_index, index)) = self.position() else {
+            return;
+        };
+
+        let key = (self.paths.data[file_index].clone(), index);
+        if !self.marked.remove(&key) {
+            self.marked.insert(key);
+        }
+    }
+
+    fn is_marked(&self) -> bool {
+        self.position().is_some_and(|(file_index, index)| {
+            self.marked
+                .contains(&(self.paths.data[file_index].clone(), index))
+        })
+    }
+
+    /// Writes the marked images to the configured marks file, one per line as the path of their
+    /// file and their index in it, separated by a tab. Nothing is written if no images are
+    /// marked.
+    pub fn write_marks(&self) -> io::Result<()> {
+        if self.marked.is_empty() {
+            return Ok(());
+        }
+
+        let mut marked: Vec<_> = self.marked.iter().collect();
+        marked.sort();
+
+        let contents: String = marked
+            .into_iter()
+            .map(|(path, index)| format!("{}\t{index}\n", path.display()))
+            .collect();
+        fs::write(&self.config.marks_path, contents)
+    }
+
+    /// Returns the frame of the current image being shown, unless it's still loading. Previews
+    /// are at a reduced resolution, so they're left out.
+    fn full_size_image(&self) -> Option<Image> {
+        self.current_image
+            .as_ref()
+            .filter(|current_image| !current_image.is_preview)
+            .map(|current_image| current_image.current_frame().image.clone())
+    }
+
+    /// Copies the current image to the clipboard.
+    pub fn copy_to_clipboard(&mut self) {
+        let Some(image) = self.full_size_image() else {
+            self.show_toast("The image hasn't loaded yet");
+            return;
+        };
+
+        // The clipboard is kept open, since on some platforms what's copied is lost once it's
+        // closed
+        let result = match &mut self.clipboard {
+            Some(clipboard) => Ok(clipboard),
+            None => Clipboard::new().map(|clipboard| self.clipboard.insert(clipboard)),
+        }
+        .map_err(|e| e.to_string())
+        .and_then(|clipboard| copy_image(clipboard, &image));
+
+        match result {
+            Ok(()) => self.show_toast("Copied the image"),
+            Err(e) => {
+                eprintln!("failed to copy the image: {e}");
+                self.show_toast("Couldn't copy the image");
+            }
+        }
+    }
+
+    /// Writes the current image to a PNG file next to the current file, named after it and the
+    /// image's index in it. The image is encoded and written in the background, and the outcome
+    /// is reported on stderr.
+    pub fn export_png(&mut self) {
+        let Some((file_index, index)) = self.position() else {
+            return;
+        };
+        let Some(image) = self.full_size_image() else {
+            self.show_toast("The image hasn't loaded yet");
+            return;
+        };
+
+        let path = &self.paths.data[file_index];
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        // Padded like the names of split images, so that exports sort in order
+        let width = self
+            .current_file
+            .as_ref()
+            .map_or(1, |current_file| current_file.len().to_string().len());
+        let out_path = path.with_file_name(format!("{stem}-{index:0width$}.png"));
+        let label = self.export_label();
+
+        self.show_toast(format!(
+            "Exporting to {}",
+            out_path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        thread::spawn(move || {
+            let result = match label {
+                Some(label) => annotation::label_image(&image, &label)
+                    .ok_or_else(|| io::Error::other("couldn't draw the label on the image")),
+                None => Ok(image),
+            }
+            .and_then(|image| {
+                image
+                    .encode(None, EncodedImageFormat::PNG, None)
+                    .ok_or_else(|| io::Error::other("couldn't encode the image as PNG"))
+            })
+            .and_then(|data| fs::write(&out_path, data.as_bytes()));
+
+            match result {
+                Ok(()) => eprintln!("wrote {}", out_path.display()),
+                Err(e) => eprintln!("failed to write {}: {e}", out_path.display()),
+            }
+        });
+    }
+
+    /// Returns the label to draw onto images exported from the current one, if exports are
+    /// labelled.
+    pub fn export_label(&self) -> Option<String> {
+        let (file_index, index) = self.position().filter(|_| self.config.label_exports)?;
+
+        Some(annotation::page_label(&self.paths.data[file_index], index))
+    }
+
+    /// Returns which end of the images was just run into, and how far through showing that it
+    /// was, from 0 to 1.
+    fn boundary_cue(&self) -> Option<(Boundary, f32)> {
+        let (boundary, hit_at) = self.boundary_hit?;
+        let elapsed = hit_at.elapsed();
+
+        (elapsed < BOUNDARY_CUE_DURATION).then(|| {
+            (
+                boundary,
+                elapsed.as_secs_f32() / BOUNDARY_CUE_DURATION.as_secs_f32(),
+            )
+        })
+    }
+
+    /// Returns how far through fading from the previous image the current one is, from 0 to 1,
+    /// or `None` if it isn't fading.
+    fn fade_progress(&self) -> Option<f32> {
+        let (_, started_at) = self.fading_out.as_ref()?;
+        let elapsed = started_at.elapsed();
+
+        (elapsed < FADE_DURATION).then(|| elapsed.as_secs_f32() / FADE_DURATION.as_secs_f32())
+    }
+
+    fn show_toast(&mut self, message: impl Into<String>) {
+        self.toast = Some(Toast::new(message));
+    }
+
+    /// Returns when the screen next needs to be redrawn without any user input, if ever.
+    pub fn redraw_deadline(&self) -> Option<Instant> {
+        let toast_expiry = self
+            .toast
+            .as_ref()
+            .filter(|toast| toast.is_visible())
+            .map(Toast::expires_at);
+
+        let preview_expiry = self
+            .file_switched_at
+            .filter(|_| self.showing_file_preview())
+            .map(|switched_at| switched_at + FILE_PREVIEW_DURATION);
+
+        let peek_expiry = self
+            .peek_started_at
+            .filter(|_| self.peeking())
+            .map(|started_at| started_at + PEEK_DURATION);
+
+        // Redrawn continuously while they fade out
+        let boundary_cue = self
+            .boundary_cue()
+            .map(|_| Instant::now() + Duration::from_millis(16));
+        let fade = self
+            .fade_progress()
+            .map(|_| Instant::now() + Duration::from_millis(16));
+        let coasting = self
+            .coasting
+            .map(|_| Instant::now() + Duration::from_millis(16));
+        let slideshow_bar = self
+            .config
+            .slideshow_bar
+            .and(self.slideshow_image_shown_at)
+            .map(|_| Instant::now() + Duration::from_millis(16));
+        // Checked every so often, since the preloader doesn't say when it's done
+        let decoding = (self.show_decoding && self.is_decoding())
+            .then(|| Instant::now() + Duration::from_millis(100));
+
+        let next_frame = self
+            .current_image
+            .as_ref()
+            .and_then(CurrentImage::next_frame_at);
+
+        toast_expiry
+            .into_iter()
+            .chain(preview_expiry)
+            .chain(peek_expiry)
+            .chain(next_frame)
+            .chain(self.next_slide_at())
+            .chain(boundary_cue)
+            .chain(fade)
+            .chain(coasting)
+            .chain(decoding)
+            .chain(slideshow_bar)
+            .min()
+    }
+
+    fn showing_file_preview(&self) -> bool {
+        self.config.preview_file_switches
+            && self
+                .file_switched_at
+                .is_some_and(|switched_at| switched_at.elapsed() < FILE_PREVIEW_DURATION)
+    }
+}
+
+pub fn render_frame(
+    screen_width: i32,
+    screen_height: i32,
+    state: &mut Screen,
+    hide_chrome: bool,
+    context: &mut DirectContext,
+    canvas: &skia_safe::Canvas,
+) {
+    state.advance_slideshow();
+
+    // Checked once everything which could have moved has, whether that was input, the
+    // slideshow, or files being dropped on the window
+    if state.config.print_on_change && state.take_changed_position().is_some() {
+        state.print_position();
+    }
+
+    render_image(screen_width, screen_height, state, context, canvas);
+
+    // Shown regardless, since it's feedback for a key press
+    if let Some((boundary, progress)) = state.boundary_cue() {
+        render_boundary_cue(boundary, progress, screen_width, screen_height, canvas);
+    }
+
+    if state.is_marked() && !hide_chrome {
+        render_mark_frame(screen_width, screen_height, canvas);
+    }
+
+    if let Some(caption) = state
+        .current_file
+        .as_ref()
+        .filter(|_| state.show_captions && !hide_chrome)
+        .and_then(CurrentFile::caption)
+    {
+        render_caption(caption, screen_width, screen_height, canvas);
+    }
+
+    // Peeking is shown regardless, since it only lasts while its key is held down
+    if state.peeking() {
+        render_peeked_cover(screen_width, screen_height, state, canvas);
+    }
+
+    // The number being typed is shown regardless, so that it can be checked before jumping
+    if let Some(typed_number) = &state.typed_number {
+        render_typed_number(typed_number, screen_width, canvas);
+    }
+
+    if hide_chrome {
+        return;
+    }
+
+    // Drawn in the same place as the number being typed, which takes its place for a moment
+    if let Some(chapter) = state
+        .current_file
+        .as_ref()
+        .filter(|_| state.typed_number.is_none())
+        .and_then(CurrentFile::chapter)
+    {
+        render_chapter_title(&chapter.title, screen_width, canvas);
+    }
+
+    if let Some(current_file) = state
+        .current_file
+        .as_ref()
+        .filter(|current_file| !current_file.is_empty())
+    {
+        let (index, len) = (current_file.index, current_file.len());
+        match state.progress_display {
+            ProgressDisplay::Hidden => {}
+            ProgressDisplay::Dots => render_progress(index, len, canvas),
+            ProgressDisplay::Fraction => render_progress_fraction(index, len, canvas),
+        }
+    }
+
+    // Both are shown in the same box, so that they don't overlap
+    if state.show_info || state.show_view_state {
+        let mut lines = Vec::new();
+        if state.show_info {
+            lines.extend(state.info_lines());
+        }
+        if state.show_view_state {
+            lines.extend(state.view_state_lines());
+        }
+        render_info(&lines, screen_width, canvas);
+    }
+
+    if let (Some(position), Some(progress)) =
+        (state.config.slideshow_bar, state.slideshow_progress())
+    {
+        render_slideshow_bar(position, progress, screen_width, screen_height, canvas);
+    }
+
+    if state.show_decoding && state.is_decoding() {
+        render_decoding_indicator(screen_width, screen_height, canvas);
+    }
+
+    if let Some(toast) = state.toast.as_ref().filter(|toast| toast.is_visible()) {
+        toast::render(toast, screen_width, screen_height, canvas);
+    }
+}
+
+fn render_image(
+    screen_width: i32,
+    screen_height: i32,
+    state: &mut Screen,
+    context: &mut DirectContext,
+    canvas: &skia_safe::Canvas,
+) {
+    // The current image fades in over the previous one, which is drawn first
+    let fade_progress = state.fade_progress();
+    match (&state.fading_out, fade_progress) {
+        (Some((previous, _)), Some(progress)) => previous.draw(1.0 - progress, canvas),
+        _ => state.fading_out = None,
+    }
+
+    if let Some(error) = &state.last_error {
+        render_error(error, screen_width, screen_height, canvas);
+        // Images which fail to decode are shown for as long as any other, rather than stopping
+        // the slideshow
+        state.start_slideshow_timer();
+        return;
+    }
+
+    // The preview is replaced with the full resolution image once navigation settles. It's
+    // kept up until the full resolution image is ready to replace it.
+    let showing_file_preview = state.showing_file_preview();
+    if state
+        .current_image
+        .as_ref()
+        .is_some_and(|current_image| current_image.is_preview && !showing_file_preview)
+    {
+        match state.cached_frames() {
+            Some(frames) => state.current_image = Some(CurrentImage::new(frames, false)),
+            None => state.request_decode(false),
+        }
+    }
+
+    let current_image = match state.current_image {
+        Some(ref mut current_image) => current_image,
+        None => {
+            if state
+                .current_file
+                .as_ref()
+                .is_none_or(CurrentFile::is_empty)
+            {
+                let (message, hint) = match state
+                    .current_file
+                    .as_ref()
+                    .map(|current_file| &current_file.file)
+                {
+                    Some(Err(e)) => (
+                        format!("Failed to open file: {e}"),
+                        "Press F5 to retry, or move to another file",
+                    ),
+                    Some(Ok(_)) => (
+                        "This file has no images".to_string(),
+                        "Move to another file to continue",
+                    ),
+                    None => (
+                        "No images to display".to_string(),
+                        "Select files containing images, or press Super+Q to quit",
+                    ),
+                };
+                render_message(&message, hint, screen_width, screen_height, canvas);
+                state.start_slideshow_timer();
+                return;
+            }
+
+            let Some(frames) = state.cached_frames() else {
+                // Decoding happens off this thread so that input is still handled in the
+                // meantime. The image is drawn once it's been decoded.
+                state.request_decode(showing_file_preview);
+                render_loading(screen_width, screen_height, canvas);
+                return;
+            };
+
+            // Navigation has settled once the full image is shown, so get ready for the next
+            // move
+            state.start_preloading();
+
+            state.current_image.insert(CurrentImage::new(frames, false))
+        }
+    };
+
+    current_image.advance();
+    let is_preview = current_image.is_preview;
+    let info = current_image.current_frame().info.clone();
+    let image = current_image.current_texture(context);
+
+    // Tiled images are drawn from an overview which is smaller than they are
+    let image_size = current_image.size();
+    let sideways = state.quarter_turns % 2 == 1;
+    // A sideways image takes up the window with its width and height swapped
+    let ISize {
+        mut width,
+        mut height,
+    } = if sideways {
+        ISize::new(image_size.height, image_size.width)
+    } else {
+        image_size
+    };
+
+    // Determine the scaling factor based on the window dimensions
+    let scale_x = screen_width as f32 / width as f32;
+    let scale_y = screen_height as f32 / height as f32;
+
+    let scale = match state.fit_mode {
+        // Use the smaller scaling factor to fit within the window
+        FitMode::Fit => scale_x.min(scale_y),
+        // Use the larger scaling factor to cover the whole window
+        FitMode::Fill => scale_x.max(scale_y),
+        // Previews are decoded at a fraction of the image's size, which this approximately
+        // undoes
+        FitMode::Actual if is_preview => 1.0 / FILE_PREVIEW_SCALE,
+        FitMode::Actual => 1.0,
+    };
+    let scale = scale * state.zoom.scale;
+
+    width = (width as f32 * scale) as i32;
+    height = (height as f32 * scale) as i32;
+
+    state.coast();
+    let (offset_x, offset_y) = (state.zoom.offset_x, state.zoom.offset_y);
+    state
+        .zoom
+        .clamp_offset(width, height, screen_width, screen_height);
+    // A flung image stops moving in whichever direction it runs into the edge of the window
+    if let Some(motion) = &mut state.coasting {
+        if state.zoom.offset_x != offset_x {
+            motion.velocity_x = 0.0;
+        }
+        if state.zoom.offset_y != offset_y {
+            motion.velocity_y = 0.0;
+        }
+    }
+
+    let center_x = screen_width as f32 / 2.0 + state.zoom.offset_x;
+    let center_y = screen_height as f32 / 2.0 + state.zoom.offset_y;
+
+    let mut paint = Paint::default();
+    if state.sharpen_monochrome && is_monochrome(&info) {
+        paint.set_image_filter(sharpen_filter());
+    }
+
+    // The image is drawn around the origin, which the canvas moves to the image's center and
+    // rotates about
+    if sideways {
+        (width, height) = (height, width);
+    }
+    let dst = Rect {
+        left: -width as f32 / 2.0,
+        top: -height as f32 / 2.0,
+        right: width as f32 / 2.0,
+        bottom: height as f32 / 2.0,
+    };
+    let screen = Rect {
+        left: -center_x,
+        top: -center_y,
+        right: screen_width as f32 - center_x,
+        bottom: screen_height as f32 - center_y,
+    };
+    let screen = unrotate(screen, state.quarter_turns);
+    let image_dst = dst;
+    let Some((src, dst)) = crop_to_screen(dst, info.dimensions(), screen) else {
+        return;
+    };
+
+    let drawn = state.last_drawn.insert(DrawnImage {
+        image,
+        src,
+        dst,
+        center: (center_x, center_y),
+        quarter_turns: state.quarter_turns,
+        paint,
+    });
+    drawn.draw(fade_progress.unwrap_or(1.0), canvas);
+    if !is_preview {
+        state.start_slideshow_timer();
+    }
+
+    render_tiles(
+        image_dst,
+        screen,
+        state,
+        fade_progress.unwrap_or(1.0),
+        context,
+        canvas,
+    );
+}
+
+/// Draws the part of an image too large to decode whole which has been decoded at full size on
+/// top of its overview, which was drawn in `image_dst`. Once the overview is drawn larger than
+/// its own size, the part of the image on screen is decoded at full size for drawing next.
+fn render_tiles(
+    image_dst: Rect,
+    screen: Rect,
+    state: &mut Screen,
+    alpha: f32,
+    context: &mut DirectContext,
+    canvas: &skia_safe::Canvas,
+) {
+    let Some((file_index, index)) = state.position() else {
+        return;
+    };
+    let Some(current_image) = &mut state.current_image else {
+        return;
+    };
+    let overview_width = current_image.current_frame().info.width();
+    let (Some(tiles), Some(overview)) = (&mut current_image.tiles, &state.last_drawn) else {
+        return;
+    };
+    let Some((src, dst)) = crop_to_screen(image_dst, tiles.size, screen) else {
+        return;
+    };
+
+    if let Some((region, image)) = &mut tiles.region {
+        // Uploaded once, rather than for every draw
+        if !image.is_texture_backed()
+            && let Some(texture) = image.new_texture_image(context, Mipmapped::No)
+        {
+            *image = texture;
+        }
+
+        // Where the whole region would be drawn, which is cropped to what's on screen
+        let scale_x = (dst.right - dst.left) / (src.right - src.left);
+        let scale_y = (dst.bottom - dst.top) / (src.bottom - src.top);
+        let region_dst = Rect {
+            left: dst.left + (region.left as f32 - src.left) * scale_x,
+            top: dst.top + (region.top as f32 - src.top) * scale_y,
+            right: dst.left + (region.right as f32 - src.left) * scale_x,
+            bottom: dst.top + (region.bottom as f32 - src.top) * scale_y,
+        };
+        let region_size = ISize::new(region.right - region.left, region.bottom - region.top);
+        if let Some((region_src, region_dst)) = crop_to_screen(region_dst, region_size, dst) {
+            DrawnImage {
+                image: image.clone(),
+                src: region_src,
+                dst: region_dst,
+                ..overview.clone()
+            }
+            .draw(alpha, canvas);
+        }
+    }
+
+    // The overview only looks blurry once it's drawn larger than its own size
+    let scale = (dst.right - dst.left) / (src.right - src.left);
+    let overview_scale = overview_width as f32 / tiles.size.width as f32;
+    if tiles.failed || tiles.pending.is_some() || scale <= overview_scale {
+        return;
+    }
+    if tiles
+        .region
+        .as_ref()
+        .is_some_and(|(region, _)| contains(*region, src))
+    {
+        return;
+    }
+
+    if let Some(region) = region_to_decode(src, tiles.size)
+        && let Some(Ok(file)) = state
+            .current_file
+            .as_ref()
+            .map(|current_file| &current_file.file)
+    {
+        state.decoder.decode_region(
+            state.paths.data[file_index].clone(),
+            file.clone(),
+            index,
+            region,
+        );
+        tiles.pending = Some(region);
+    }
+}
+
+/// Returns whether `rect` is entirely within `region`.
+fn contains(region: IRect, rect: Rect) -> bool {
+    region.left as f32 <= rect.left
+        && region.top as f32 <= rect.top
+        && rect.right <= region.right as f32
+        && rect.bottom <= region.bottom as f32
+}
+
+/// Returns the part of an image of `size` to decode at full size for drawing `src` of it, padded
+/// to leave room for panning, or `None` if even `src` alone is too large to decode. It starts
+/// at even coordinates, as [`gallery_desktop::decode_region`] needs.
+fn region_to_decode(src: Rect, size: ISize) -> Option<IRect> {
+    [REGION_PADDING, 0.0].into_iter().find_map(|padding| {
+        let pad_x = (src.right - src.left) * padding;
+        let pad_y = (src.bottom - src.top) * padding;
+        let region = IRect {
+            left: ((src.left - pad_x).floor() as i32).max(0) & !1,
+            top: ((src.top - pad_y).floor() as i32).max(0) & !1,
+            right: ((src.right + pad_x).ceil() as i32).min(size.width),
+            bottom: ((src.bottom + pad_y).ceil() as i32).min(size.height),
+        };
+        let region_size = ISize::new(region.right - region.left, region.bottom - region.top);
+
+        (gallery_desktop::pixel_count(region_size) <= MAX_REGION_PIXELS).then_some(region)
+    })
+}
+
+/// Maps `rect` through the inverse of a rotation about the origin by `quarter_turns`
+/// clockwise quarter turns.
+fn unrotate(rect: Rect, quarter_turns: u8) -> Rect {
+    let unrotate_point = |x: f32, y: f32| match quarter_turns % 4 {
+        0 => (x, y),
+        1 => (y, -x),
+        2 => (-x, -y),
+        _ => (-y, x),
+    };
+    let (x1, y1) = unrotate_point(rect.left, rect.top);
+    let (x2, y2) = unrotate_point(rect.right, rect.bottom);
+
+    Rect {
+        left: x1.min(x2),
+        top: y1.min(y2),
+        right: x1.max(x2),
+        bottom: y1.max(y2),
+    }
+}
+
+/// Crops `dst`, the rectangle an image of `image_size` would be drawn in, to the part of it
+/// that's within `screen`. Returns the part of the image to draw along with where to draw it,
+/// or `None` if none of it is on screen.
+fn crop_to_screen(dst: Rect, image_size: ISize, screen: Rect) -> Option<(Rect, Rect)> {
+    let visible = Rect {
+        left: dst.left.max(screen.left),
+        top: dst.top.max(screen.top),
+        right: dst.right.min(screen.right),
+        bottom: dst.bottom.min(screen.bottom),
+    };
+    if visible.left >= visible.right || visible.top >= visible.bottom {
+        return None;
+    }
+
+    let scale_x = image_size.width as f32 / (dst.right - dst.left);
+    let scale_y = image_size.height as f32 / (dst.bottom - dst.top);
+    let src = Rect {
+        left: (visible.left - dst.left) * scale_x,
+        top: (visible.top - dst.top) * scale_y,
+        right: (visible.right - dst.left) * scale_x,
+        bottom: (visible.bottom - dst.top) * scale_y,
+    };
+
+    Some((src, visible))
+}
+
+/// Puts the pixels of `image` on `clipboard`.
+fn copy_image(clipboard: &mut Clipboard, image: &Image) -> Result<(), String> {
+    let ISize { width, height } = image.dimensions();
+    let (width, height) = (
+        usize::try_from(width).unwrap(),
+        usize::try_from(height).unwrap(),
+    );
+
+    // The clipboard takes unpremultiplied RGBA, regardless of how the image was decoded
+    let info = ImageInfo::new(
+        image.dimensions(),
+        ColorType::RGBA8888,
+        AlphaType::Unpremul,
+        None,
+    );
+    let row_bytes = width * 4;
+    let mut pixels = vec![0u8; row_bytes * height];
+    if !image.read_pixels(&info, &mut pixels, row_bytes, (0, 0), CachingHint::Allow) {
+        return Err("couldn't read the image's pixels".to_string());
+    }
+
+    clipboard
+        .set_image(ImageData {
+            width,
+            height,
+            bytes: pixels.into(),
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// Returns whether an image is grayscale or bilevel, which codecs decode to a single channel.
+fn is_monochrome(info: &ImageInfo) -> bool {
+    info.color_type() == ColorType::Gray8
+}
+
+/// Returns a filter which sharpens edges, such as the outlines of text.
+fn sharpen_filter() -> Option<ImageFilter> {
+    #[rustfmt::skip]
+    const KERNEL: [f32; 9] = [
+         0.0, -1.0,  0.0,
+        -1.0,  5.0, -1.0,
+         0.0, -1.0,  0.0,
+    ];
+
+    image_filters::matrix_convolution(
+        (3, 3),
+        &KERNEL,
+        1.0,
+        0.0,
+        (1, 1),
+        TileMode::Clamp,
+        false,
+        None,
+        None,
+    )
+}
+
+fn render_error(
+    error: &LoadError,
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    render_message(
+        &format!("Failed to load image: {error}"),
+        "Press F5 to retry",
+        screen_width,
+        screen_height,
+        canvas,
+    );
+}
+
+/// Renders a message centered on the screen, with a less prominent hint below it.
+fn render_message(
+    message: &str,
+    hint: &str,
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+
+    let center_x = screen_width as f32 / 2.0;
+    let center_y = screen_height as f32 / 2.0;
+
+    let font = text::font(40.0);
+    let (width, _) = font.measure_str(message, None);
+    paint.set_color(Color::WHITE);
+    canvas.draw_str(message, (center_x - width / 2.0, center_y), &font, &paint);
+
+    let font = text::font(24.0);
+    let (width, _) = font.measure_str(hint, None);
+    paint.set_color(Color::GRAY);
+    canvas.draw_str(
+        hint,
+        (center_x - width / 2.0, center_y + 48.0),
+        &font,
+        &paint,
+    );
+}
+
+/// Shows that the current image is being decoded.
+fn render_loading(screen_width: i32, screen_height: i32, canvas: &skia_safe::Canvas) {
+    const MESSAGE: &str = "Loading\u{2026}";
+
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+    paint.set_color(Color::GRAY);
+
+    let font = text::font(24.0);
+    let (width, _) = font.measure_str(MESSAGE, None);
+    canvas.draw_str(
+        MESSAGE,
+        (
+            (screen_width as f32 - width) / 2.0,
+            screen_height as f32 / 2.0,
+        ),
+        &font,
+        &paint,
+    );
+}
+
+fn render_peeked_cover(
+    screen_width: i32,
+    screen_height: i32,
+    state: &mut Screen,
+    canvas: &skia_safe::Canvas,
+) {
+    const MARGIN: f32 = 32.0;
+    const BORDER: f32 = 4.0;
+
+    let max_width = screen_width / 4;
+    let max_height = screen_height / 4;
+
+    // The next file may have been removed since peeking started
+    let Some(path) = state.paths.data.get(state.paths.index + 1).cloned() else {
+        return;
+    };
+
+    // Covers are decoded off the render thread, so nothing is shown until it's ready
+    if state
+        .peeked_cover
+        .as_ref()
+        .is_none_or(|cover| cover.path != path)
+    {
+        state.request_cover(&path, ISize::new(max_width, max_height));
+        return;
+    }
+
+    let Some(DecodedImage { image, info }) = state
+        .peeked_cover
+        .as_ref()
+        .and_then(|cover| cover.image.as_ref())
+    else {
+        return;
+    };
+
+    let ISize { width, height } = info.dimensions();
+    let scale = (max_width as f32 / width as f32).min(max_height as f32 / height as f32);
+    let width = width as f32 * scale;
+    let height = height as f32 * scale;
+
+    let rect = Rect {
+        left: screen_width as f32 - MARGIN - width,
+        top: screen_height as f32 - MARGIN - height,
+        right: screen_width as f32 - MARGIN,
+        bottom: screen_height as f32 - MARGIN,
+    };
+
+    let mut border = Paint::default();
+    border.set_color(Color::WHITE);
+    canvas.draw_rect(
+        Rect {
+            left: rect.left - BORDER,
+            top: rect.top - BORDER,
+            right: rect.right + BORDER,
+            bottom: rect.bottom + BORDER,
+        },
+        &border,
+    );
+
+    canvas.draw_image_rect_with_sampling_options(
+        image,
+        None,
+        rect,
+        SamplingOptions {
+            max_aniso: 0,
+            use_cubic: false,
+            cubic: skia_safe::CubicResampler { b: 0.0, c: 0.0 },
+            filter: skia_safe::FilterMode::Linear,
+            mipmap: skia_safe::MipmapMode::None,
+        },
+        &Paint::default(),
+    );
+}
+
+fn render_caption(
+    caption: &str,
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    const PADDING: f32 = 16.0;
+
+    let font = text::font(24.0);
+    let (_, metrics) = font.metrics();
+    let line_height = metrics.descent - metrics.ascent + metrics.leading;
+
+    let lines = text::wrap(caption, &font, screen_width as f32 - 2.0 * PADDING);
+
+    let top = screen_height as f32 - lines.len() as f32 * line_height - 2.0 * PADDING;
+
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_rect(
+        Rect {
+            left: 0.0,
+            top,
+            right: screen_width as f32,
+            bottom: screen_height as f32,
+        },
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = top + PADDING - metrics.ascent + i as f32 * line_height;
+        canvas.draw_str(line, (PADDING, baseline), &font, &paint);
+    }
+}
+
+/// Highlights the edge of the screen for `boundary`, fading it out as `progress` goes from 0
+/// to 1. The start is at the top and the end is at the bottom, like moving down a page.
+fn render_boundary_cue(
+    boundary: Boundary,
+    progress: f32,
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    const HEIGHT: f32 = 12.0;
+
+    let alpha = (0xAA as f32 * (1.0 - progress)) as u8;
+    let mut paint = Paint::default();
+    paint.set_color(Color::WHITE);
+    paint.set_alpha(alpha);
+
+    let top = match boundary {
+        Boundary::Start => 0.0,
+        Boundary::End => screen_height as f32 - HEIGHT,
+    };
+    canvas.draw_rect(
+        Rect {
+            left: 0.0,
+            top,
+            right: screen_width as f32,
+            bottom: top + HEIGHT,
+        },
+        &paint,
+    );
+}
+
+/// Draws a bar along an edge of the screen which fills up as `progress` goes from 0 to 1, for
+/// how long is left until the slideshow moves on.
+fn render_slideshow_bar(
+    position: BarPosition,
+    progress: f32,
+    screen_width: i32,
+    screen_height: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    const HEIGHT: f32 = 4.0;
+
+    let mut paint = Paint::default();
+    paint.set_color(Color::WHITE);
+    paint.set_alpha(0xAA);
+
+    let top = match position {
+        BarPosition::Top => 0.0,
+        BarPosition::Bottom => screen_height as f32 - HEIGHT,
+    };
+    canvas.draw_rect(
+        Rect {
+            left: 0.0,
+            top,
+            right: screen_width as f32 * progress,
+            bottom: top + HEIGHT,
+        },
+        &paint,
+    );
+}
+
+/// Draws a dot in the bottom right corner, to show that images are being decoded in the
+/// background.
+fn render_decoding_indicator(screen_width: i32, screen_height: i32, canvas: &skia_safe::Canvas) {
+    const RADIUS: f32 = 5.0;
+    const MARGIN: f32 = 16.0;
+
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+    paint.set_color(Color::WHITE);
+    paint.set_alpha(0xAA);
+
+    canvas.draw_circle(
+        (
+            screen_width as f32 - MARGIN - RADIUS,
+            screen_height as f32 - MARGIN - RADIUS,
+        ),
+        RADIUS,
+        &paint,
+    );
+}
+
+/// Draws a frame around the edges of the screen, to show that the current image is marked.
+fn render_mark_frame(screen_width: i32, screen_height: i32, canvas: &skia_safe::Canvas) {
+    let mut paint = Paint::default();
+    paint.set_color(0xFFFFC107);
+    paint.set_style(PaintStyle::Stroke);
+    paint.set_stroke_width(MARK_FRAME_WIDTH);
+
+    // Inset so that the whole frame is on screen
+    let inset = MARK_FRAME_WIDTH / 2.0;
+    canvas.draw_rect(
+        Rect {
+            left: inset,
+            top: inset,
+            right: screen_width as f32 - inset,
+            bottom: screen_height as f32 - inset,
+        },
+        &paint,
+    );
+}
+
+/// Draws `lines` in the top right corner, out of the way of the progress indicator.
+fn render_info(lines: &[String], screen_width: i32, canvas: &skia_safe::Canvas) {
+    const PADDING: f32 = 12.0;
+    const MARGIN: f32 = 16.0;
+
+    if lines.is_empty() {
+        return;
+    }
+
+    let font = text::font(20.0);
+    let (_, metrics) = font.metrics();
+    let line_height = metrics.descent - metrics.ascent + metrics.leading;
+
+    let text_width = lines
+        .iter()
+        .map(|line| font.measure_str(line, None).0)
+        .fold(0.0, f32::max);
+    let box_width = text_width + 2.0 * PADDING;
+    let box_height = lines.len() as f32 * line_height + 2.0 * PADDING;
+
+    let left = (screen_width as f32 - MARGIN - box_width).max(0.0);
+    let top = MARGIN;
+
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_round_rect(
+        Rect {
+            left,
+            top,
+            right: left + box_width,
+            bottom: top + box_height,
+        },
+        8.0,
+        8.0,
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = top + PADDING - metrics.ascent + i as f32 * line_height;
+        canvas.draw_str(line, (left + PADDING, baseline), &font, &paint);
+    }
+}
+
+fn render_typed_number(typed_number: &str, screen_width: i32, canvas: &skia_safe::Canvas) {
+    render_top_message(
+        &format!("Go to image {typed_number}"),
+        28.0,
+        screen_width,
+        canvas,
+    );
+}
+
+fn render_chapter_title(title: &str, screen_width: i32, canvas: &skia_safe::Canvas) {
+    render_top_message(title, 20.0, screen_width, canvas);
+}
+
+/// Draws `message` in a box at the top middle of the screen.
+fn render_top_message(
+    message: &str,
+    font_size: f32,
+    screen_width: i32,
+    canvas: &skia_safe::Canvas,
+) {
+    const PADDING: f32 = 16.0;
+    const MARGIN: f32 = 16.0;
+
+    let font = text::font(font_size);
+    let (text_width, _) = font.measure_str(message, None);
+    let (_, metrics) = font.metrics();
+
+    let box_width = text_width + 2.0 * PADDING;
+    let box_height = metrics.descent - metrics.ascent + 2.0 * PADDING;
+
+    // Drawn at the top, between the progress indicator and the info overlay
+    let left = (screen_width as f32 - box_width) / 2.0;
+    let top = MARGIN;
+
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_round_rect(
+        Rect {
+            left,
+            top,
+            right: left + box_width,
+            bottom: top + box_height,
+        },
+        8.0,
+        8.0,
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    canvas.draw_str(
+        message,
+        (left + PADDING, top + PADDING - metrics.ascent),
+        &font,
+        &paint,
+    );
+}
+
+fn render_progress(index: usize, len: usize, canvas: &skia_safe::Canvas) {
+    let progress = progress_dots(index, len);
+
+    let mut paint = Paint::default();
+    paint.set_color(Color::WHITE);
+
+    const RADIUS: f32 = 16.0;
+    const SPACING: f32 = 8.0;
+
+    for i in 0..progress {
+        let top_offset = (2.0 * RADIUS + SPACING) * (1 + i / 3) as f32;
+        let left_offset = (2.0 * RADIUS + SPACING) * (1 + i % 3) as f32;
+        canvas.draw_circle((left_offset, top_offset), RADIUS, &paint);
+    }
+}
+
+/// Returns how many of the ten progress dots to fill at the image at `index` of `len`.
+fn progress_dots(index: usize, len: usize) -> usize {
+    // Counting the current image, so that all ten dots are shown at the last image
+    (index + 1) * 10 / len
+}
+
+fn render_progress_fraction(index: usize, len: usize, canvas: &skia_safe::Canvas) {
+    const PADDING: f32 = 12.0;
+    const MARGIN: f32 = 16.0;
+
+    let message = format!("{} / {len}", index + 1);
+
+    let font = text::font(24.0);
+    let (text_width, _) = font.measure_str(&message, None);
+    let (_, metrics) = font.metrics();
+
+    let mut paint = Paint::default();
+    paint.set_color(0xCC000000);
+    canvas.draw_round_rect(
+        Rect {
+            left: MARGIN,
+            top: MARGIN,
+            right: MARGIN + text_width + 2.0 * PADDING,
+            bottom: MARGIN + metrics.descent - metrics.ascent + 2.0 * PADDING,
+        },
+        8.0,
+        8.0,
+        &paint,
+    );
+
+    paint.set_color(Color::WHITE);
+    paint.set_anti_alias(true);
+    canvas.draw_str(
+        &message,
+        (MARGIN + PADDING, MARGIN + PADDING - metrics.ascent),
+        &font,
+        &paint,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use gallery_desktop::OfcWriter;
+
+    use super::*;
+
+    /// Writes containers with the given numbers of images to a new directory named after
+    /// `test`, returning the directory and the containers' paths.
+    fn write_containers(test: &str, image_counts: &[usize]) -> (PathBuf, Vec<PathBuf>) {
+        let dir = env::temp_dir().join(format!("gallery-desktop-{test}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let paths = image_counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let mut writer = OfcWriter::new();
+                for image in 0..count {
+                    writer.add_image(&[u8::try_from(image).unwrap()]);
+                }
+
+                let path = dir.join(format!("{i}.ofc"));
+                writer
+                    .finish(&mut fs::File::create(&path).unwrap())
+                    .unwrap();
+                path
+            })
+            .collect();
+
+        (dir, paths)
+    }
+
+    fn screen(paths: Vec<PathBuf>) -> Screen {
+        Screen::new(
+            paths,
+            Config::default(),
+            ImageCache::new(0),
+            Decoder::new(|_| true),
+        )
+    }
+
+    #[test]
+    fn chapter_keys_move_between_the_starts_of_chapters() {
+        let dir = env::temp_dir().join(format!("gallery-desktop-chapters-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chapters.ofc");
+
+        let mut writer = OfcWriter::new();
+        for image in 0..6 {
+            if image % 2 == 0 {
+                writer.start_chapter(&format!("Chapter {}", image / 2 + 1));
+            }
+            writer.add_image(&[image]);
+        }
+        writer
+            .finish(&mut fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut screen = screen(vec![path]);
+        fs::remove_dir_all(dir).unwrap();
+
+        let chapter_title = |screen: &Screen| {
+            screen
+                .current_file
+                .as_ref()?
+                .chapter()
+                .map(|c| c.title.clone())
+        };
+
+        assert!(screen.next_chapter());
+        assert_eq!(screen.position(), Some((0, 2)));
+        assert_eq!(chapter_title(&screen).as_deref(), Some("Chapter 2"));
+
+        screen.next_image();
+        assert!(screen.previous_chapter());
+        assert_eq!(screen.position(), Some((0, 2)));
+        assert!(screen.previous_chapter());
+        assert_eq!(screen.position(), Some((0, 0)));
+        assert!(!screen.previous_chapter());
+
+        screen.last_image();
+        assert_eq!(chapter_title(&screen).as_deref(), Some("Chapter 3"));
+        assert!(!screen.next_chapter());
+    }
+
+    #[test]
+    fn position_changes_are_taken_once_however_they_happen() {
+        let (dir, paths) = write_containers("position-changes", &[2, 2]);
+        let mut screen = screen(paths[..1].to_vec());
+        assert_eq!(screen.take_changed_position(), None);
+
+        screen.next_image();
+        assert_eq!(screen.take_changed_position(), Some((0, 1)));
+        assert_eq!(screen.take_changed_position(), None);
+
+        screen.add_files(paths[1..].to_vec());
+        assert_eq!(screen.take_changed_position(), Some((1, 0)));
+
+        // Moving away and back again between checks isn't a change
+        screen.next_image();
+        screen.previous_image();
+        assert_eq!(screen.take_changed_position(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn switching_back_to_a_file_restores_its_position() {
+        let (dir, paths) = write_containers("restores-position", &[3, 3]);
+        let mut screen = screen(paths);
+
+        screen.jump_to(2);
+        // Files are read right to left, so the previous file is the one after it in the list
+        screen.previous_file();
+        assert_eq!(screen.position(), Some((1, 0)));
+
+        screen.next_file();
+        assert_eq!(screen.position(), Some((0, 2)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn positions_follow_files_when_removing_one_shifts_the_others() {
+        let (dir, paths) = write_containers("positions-after-remove", &[3, 3, 3]);
+        let mut screen = screen(paths.clone());
+
+        screen.go_to(1, Target::Index(1));
+        screen.go_to(2, Target::Index(2));
+        screen.go_to(0, Target::LastLeftAt);
+        screen.remove_file();
+
+        // The second file is now the first
+        assert_eq!(screen.paths.data, paths[1..]);
+        assert_eq!(screen.position(), Some((0, 1)));
+
+        screen.go_to(1, Target::LastLeftAt);
+        assert_eq!(screen.position(), Some((1, 2)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn preloading_follows_the_way_navigation_moves() {
+        let (dir, paths) = write_containers("preload-direction", &[8, 2]);
+        let mut screen = screen(paths.clone());
+        screen.jump_to(4);
+
+        let images = |screen: &Screen| {
+            screen
+                .images_to_preload()
+                .into_iter()
+                .map(|(path, index)| (paths.iter().position(|p| *p == path).unwrap(), index))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(images(&screen), [(0, 5), (0, 3)]);
+
+        screen.next_image();
+        assert_eq!(images(&screen), [(0, 6), (0, 7), (1, 0)]);
+
+        screen.previous_image();
+        assert_eq!(images(&screen), [(0, 3), (0, 2), (0, 1)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn slideshow_waits_for_images_to_be_drawn_before_timing_them() {
+        let (dir, paths) = write_containers("slideshow-timer", &[3]);
+        let mut screen = screen(paths);
+
+        screen.toggle_slideshow();
+        assert_eq!(screen.next_slide_at(), None);
+
+        screen.start_slideshow_timer();
+        assert!(screen.next_slide_at().is_some());
+
+        screen.next_image();
+        assert_eq!(screen.next_slide_at(), None);
+        assert_eq!(screen.slideshow_progress(), Some(0.0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn short_files_are_preloaded_whole_nearest_first() {
+        let (dir, paths) = write_containers("preload-whole-file", &[4, 8]);
+        let mut screen = screen(paths.clone());
+        screen.config.preload_file_under = Some(5);
+        screen.jump_to(1);
+
+        let indices = |screen: &Screen| {
+            screen
+                .spare_images_to_preload()
+                .into_iter()
+                .map(|(_, index)| index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indices(&screen), [0, 2, 3]);
+
+        screen.go_to(1, Target::Index(0));
+        assert_eq!(indices(&screen), []);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn progress_dots_fill_as_the_file_is_read() {
+        assert_eq!(progress_dots(0, 100), 0);
+        assert_eq!(progress_dots(49, 100), 5);
+        assert_eq!(progress_dots(99, 100), 10);
+    }
+
+    #[test]
+    fn progress_dots_are_all_filled_at_the_last_image_of_short_files() {
+        assert_eq!(progress_dots(0, 1), 10);
+        assert_eq!(progress_dots(0, 3), 3);
+        assert_eq!(progress_dots(1, 3), 6);
+        assert_eq!(progress_dots(2, 3), 10);
+    }
+
+    #[test]
+    fn regions_to_decode_are_padded_within_the_image_from_even_coordinates() {
+        let src = Rect {
+            left: 1001.0,
+            top: 2001.0,
+            right: 2001.0,
+            bottom: 2501.0,
+        };
+
+        assert_eq!(
+            region_to_decode(src, ISize::new(2200, 10000)),
+            Some(IRect {
+                left: 500,
+                top: 1750,
+                right: 2200,
+                bottom: 2751,
+            })
+        );
+    }
+
+    #[test]
+    fn regions_to_decode_are_left_unpadded_or_out_when_they_are_too_large() {
+        let size = ISize::new(100_000, 100_000);
+        let src = Rect {
+            left: 0.0,
+            top: 0.0,
+            right: 6000.0,
+            bottom: 6000.0,
+        };
+        assert_eq!(
+            region_to_decode(src, size),
+            Some(IRect {
+                left: 0,
+                top: 0,
+                right: 6000,
+                bottom: 6000,
+            })
+        );
+
+        let src = Rect {
+            right: 10000.0,
+            bottom: 10000.0,
+            ..src
+        };
+        assert_eq!(region_to_decode(src, size), None);
     }
 }
@@ -0,0 +1,175 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+};
+
+/// Where the window was and how large it was when it was last closed in windowed mode, in
+/// physical pixels, so that it can be opened the same way again.
+#[derive(Debug, PartialEq)]
+pub struct WindowGeometry {
+    pub size: PhysicalSize<u32>,
+    /// The position of the window's top left corner, including its decorations. It's `None` on
+    /// platforms where windows can't tell where they are, like Wayland.
+    pub position: Option<PhysicalPosition<i32>>,
+}
+
+impl WindowGeometry {
+    /// Reads the geometry which was saved when the window was last closed, if there is one.
+    pub fn load() -> Option<Self> {
+        let path = path()?;
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let geometry = Self::parse(&contents);
+                if geometry.is_none() {
+                    eprintln!("ignoring {}, which isn't a window geometry", path.display());
+                }
+                geometry
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("failed to read {}: {e}", path.display());
+                }
+                None
+            }
+        }
+    }
+
+    /// Writes the geometry for the next launch to open the window with.
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::other("couldn't find the home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&path, self.format())
+    }
+
+    /// Parses a width and height on the first line, optionally followed by an x and y position
+    /// on the second.
+    fn parse(s: &str) -> Option<Self> {
+        let mut lines = s.lines();
+
+        let (width, height) = parse_pair(lines.next()?)?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let position = match lines.next() {
+            Some(line) => {
+                let (x, y) = parse_pair(line)?;
+                Some(PhysicalPosition::new(x, y))
+            }
+            None => None,
+        };
+
+        Some(Self {
+            size: PhysicalSize::new(width, height),
+            position,
+        })
+    }
+
+    fn format(&self) -> String {
+        let mut s = format!("{} {}\n", self.size.width, self.size.height);
+        if let Some(PhysicalPosition { x, y }) = self.position {
+            s.push_str(&format!("{x} {y}\n"));
+        }
+
+        s
+    }
+
+    /// Returns whether the middle of the window is on one of `monitors`, or `true` if its
+    /// position isn't known. A window whose monitor was disconnected since it was saved would
+    /// otherwise be opened out of sight.
+    pub fn is_on_screen(&self, mut monitors: impl Iterator<Item = MonitorHandle>) -> bool {
+        let Some(position) = self.position else {
+            return true;
+        };
+
+        let x = i64::from(position.x) + i64::from(self.size.width / 2);
+        let y = i64::from(position.y) + i64::from(self.size.height / 2);
+        monitors.any(|monitor| {
+            let PhysicalPosition { x: left, y: top } = monitor.position();
+            let PhysicalSize { width, height } = monitor.size();
+
+            (i64::from(left)..i64::from(left) + i64::from(width)).contains(&x)
+                && (i64::from(top)..i64::from(top) + i64::from(height)).contains(&y)
+        })
+    }
+}
+
+/// Returns the size nearest to `size` with the aspect ratio `ratio`. Whichever of the width and
+/// height changed more from `previous` is kept, since that's the edge being dragged.
+pub fn with_aspect_ratio(
+    size: PhysicalSize<u32>,
+    previous: PhysicalSize<u32>,
+    ratio: f32,
+) -> PhysicalSize<u32> {
+    if size.width.abs_diff(previous.width) >= size.height.abs_diff(previous.height) {
+        let height = (size.width as f32 / ratio).round() as u32;
+        PhysicalSize::new(size.width, height.max(1))
+    } else {
+        let width = (size.height as f32 * ratio).round() as u32;
+        PhysicalSize::new(width.max(1), size.height)
+    }
+}
+
+fn parse_pair<T: std::str::FromStr>(line: &str) -> Option<(T, T)> {
+    let (a, b) = line.trim().split_once(' ')?;
+
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+/// Returns the path of the file that the geometry is saved in, in the state directory from the
+/// XDG base directory specification.
+fn path() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(state_dir.join("gallery-desktop").join("window"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_reads_back_as_it_was_written() {
+        for position in [Some(PhysicalPosition::new(-8, 1080)), None] {
+            let geometry = WindowGeometry {
+                size: PhysicalSize::new(1280, 720),
+                position,
+            };
+
+            assert_eq!(WindowGeometry::parse(&geometry.format()), Some(geometry));
+        }
+    }
+
+    #[test]
+    fn aspect_ratio_keeps_the_side_being_resized() {
+        let previous = PhysicalSize::new(1600, 900);
+
+        assert_eq!(
+            with_aspect_ratio(PhysicalSize::new(1920, 910), previous, 16.0 / 9.0),
+            PhysicalSize::new(1920, 1080)
+        );
+        assert_eq!(
+            with_aspect_ratio(PhysicalSize::new(1610, 450), previous, 16.0 / 9.0),
+            PhysicalSize::new(800, 450)
+        );
+    }
+
+    #[test]
+    fn geometry_without_a_usable_size_is_ignored() {
+        assert_eq!(WindowGeometry::parse(""), None);
+        assert_eq!(WindowGeometry::parse("0 720\n"), None);
+        assert_eq!(WindowGeometry::parse("1280x720\n"), None);
+    }
+}